@@ -0,0 +1,12 @@
+use balance::nutrition::cli;
+
+fn main() -> () {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match cli::run(&args) {
+        Ok(output) => println!("{output}"),
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        },
+    }
+}
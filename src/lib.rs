@@ -0,0 +1,2 @@
+// the nutrition code, shared by the app and the nutrition command line
+pub mod nutrition;
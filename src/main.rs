@@ -1,9 +1,23 @@
 use std::collections::HashMap;
 use leptos::prelude::*;
 use leptos::web_sys;
+use leptos::task::spawn_local;
+use gloo_timers::future::TimeoutFuture;
+use itertools::Itertools;
+use leptos_use::storage::use_local_storage;
+use codee::string::JsonSerdeCodec;
 
-mod nutrition;
-use nutrition::{Food, Nutrient, get_foods, lookup_food, sum_nutrients, recommend_foods, get_highest_and_lowest_nutrients, format_float};
+use balance::nutrition::{Food, Nutrient, NutrientKind, get_foods, is_over_limit, sum_nutrients, recommendable_foods, recommend_portions, explain_recommendation, RecommendSettings, Basis, BASES, basis_name, nutrient_amount, rich_in, typical_portion, portion_food, find_excesses, reduced_portion, explain_excess, find_substitutions, explain_substitution, get_highest_and_lowest_nutrients, format_float};
+use balance::nutrition::scoring::{SCORERS, scorer_name, scorer_description};
+use balance::nutrition::plan::{Meal, MealPlan, PlanSettings, PLAN_TIME_LIMIT, start_plan, plan_next_meal, plan_progress, finish_plan, plan_energy, keep_to_carbs, meal_carbohydrate, day_foods, daily_average, plan_text, meal_name};
+use balance::nutrition::shopping::{ListFormat, LIST_FORMATS, list_format_name, shopping_list, export_list, format_amount};
+use balance::nutrition::search::{SearchIndex, SearchSettings, Usage, build_index, group_variants, highlight};
+use balance::nutrition::filters::{parse_typed_filters, passes, search_query, filtered_amounts};
+use balance::nutrition::query::{parse_food_query, run_query, explain_error};
+use balance::nutrition::history::{DayTotals, AveragingSettings, record_day, forget_day, effective_sum, rolling_average, food_usage};
+use balance::nutrition::optimise::{Constraints, Objective, objective_name, optimise_diet, remaining_energy, targets_met};
+use balance::nutrition::diet::{Diet, Tag, DietarySettings, ALLERGENS, DIETS, diet_name, tag_name, is_allowed};
+use balance::nutrition::goals::{Profile, Sex, NutrientGoal, Preset, ACTIVITY_LEVELS, PRESETS, activity_description, apply_profile, apply_custom_goals, apply_preset, basal_metabolic_rate, total_energy_expenditure, preset_name, preset_description, preset_highlights, preset_carb_budget};
 
 fn get_url(path: String) -> String {
     let window = web_sys::window().expect("Missing Window");
@@ -20,30 +34,62 @@ async fn get_data() -> Result<(Vec<Nutrient>, Vec<Food>)> {
     Ok((nutrients, foods))
 }
 
+// lets any nutrient row open the foods rich in that nutrient
+#[derive(Clone, Copy)]
+struct FindRichFoods(WriteSignal<Option<Nutrient>>);
+
 #[component]
 fn NutrientRow(
     nutrient: Nutrient,
     nutrient_value: f32,
 ) -> impl IntoView {
     let percentage = 100. * nutrient_value / nutrient.recommended_intake;
-    let color = if nutrient.recommended_intake > 0.1 && percentage >= 20. {
+    let color = if is_over_limit(&nutrient, nutrient_value) {
+        "#f80"
+    } else if nutrient.kind == NutrientKind::Target && nutrient.recommended_intake > 0.1 && percentage >= 20. {
         "#0d0"
     } else {
         "unset"
     };
+    let limit_prefix = match nutrient.kind {
+        NutrientKind::Limit => "max ",
+        _ => "",
+    };
+    let warning = if is_over_limit(&nutrient, nutrient_value) {
+        " ⚠️"
+    } else {
+        ""
+    };
     view! {
         <tr
             style="grid-column: 1/4; border: none; border-bottom: 1px solid var(--fg); margin: 0.1rem 0; opacity: 0.7;"
         />
-        <p style:color={color}> { nutrient.display_name.clone() } </p>
+        { match use_context::<FindRichFoods>() {
+            Some(FindRichFoods(set_rich_nutrient)) => {
+                let n = nutrient.clone();
+                view! {
+                    <button
+                        style="padding: 0; border-radius: 0;"
+                        class="hover-line"
+                        title={ format!("Find foods rich in {}", nutrient.display_name) }
+                        on:click:target=move |_| set_rich_nutrient.set(Some(n.clone()))
+                    >
+                        <p style:color={color}> { nutrient.display_name.clone() }{ warning } </p>
+                    </button>
+                }.into_any()
+            },
+            None => view! {
+                <p style:color={color}> { nutrient.display_name.clone() }{ warning } </p>
+            }.into_any(),
+        } }
         <p style="text-align: right;">
             { format_float(nutrient_value) }{ nutrient.units.clone() }
         </p>
         {
-            if nutrient.recommended_intake > 0.1 {
+            if nutrient.recommended_intake > 0.1 && nutrient.kind != NutrientKind::Informational {
                 view! {
                     <p style="text-align: right;">
-                        { nutrient.recommended_intake }{ nutrient.units.clone() }
+                        { limit_prefix }{ nutrient.recommended_intake }{ nutrient.units.clone() }
                         " | "
                         <span style:color={color} >
                             { format!( "{:.0}", percentage ) }"%"
@@ -132,13 +178,19 @@ fn Match(
     food: Food,
     nutrients: Vec<Nutrient>,
     mut on_remove: Option<impl FnMut() -> () + 'static>,
+    mut on_add: Option<impl FnMut() -> () + 'static>,
+    // finds healthier foods to swap this one for, with what swapping would
+    // change, only once the food is opened since it searches every food
+    find_swaps: Option<impl Fn() -> Vec<(Food, String)> + Send + Sync + 'static>,
+    on_swap: Option<impl Fn(Food) -> () + Copy + Send + Sync + 'static>,
 ) -> impl IntoView {
     let (modal_open, set_modal_open) = signal(false);
     let show_x = on_remove.is_some();
+    let show_plus = on_add.is_some();
     let (highest_nutrient, _) = get_highest_and_lowest_nutrients(nutrients.clone(), food.nutrients.clone());
     view! {
         <div
-            style="padding: 0 0.6rem 0 1rem; border: 1px solid var(--fg); border-radius: 2rem; display: grid; grid-template-columns: max-content auto max-content max-content; gap: 0.25rem; align-items: center;"
+            style="padding: 0 0.6rem 0 1rem; border: 1px solid var(--fg); border-radius: 2rem; display: grid; grid-template-columns: max-content auto max-content max-content max-content; gap: 0.25rem; align-items: center;"
             style:background=if show_x { "var(--bg2)" } else { "unset" }
         >
             <p style="transform: scale(1.2); margin-right: 0.32rem;">
@@ -152,7 +204,7 @@ fn Match(
                 <p
                     style="overflow: hidden; text-overflow: ellipsis; white-space: nowrap; font-size: .9rem;"
                 >
-                    { food.display_name.clone() }
+                    { format_float(food.grams) }" g of "{ food.display_name.clone() }
                 </p>
                 <img
                     src={get_url("/assets/info.svg".to_string())}
@@ -163,6 +215,17 @@ fn Match(
             <p style="font-weight: bold; font-size: 0.75rem;">
                 "📊 "{ highest_nutrient.display_name }
             </p>
+            <button
+                on:click:target={move |_| if let Some(ref mut f) = on_add { f(); }}
+                style="padding: 0;"
+                style:display=move || if show_plus { "unset" } else { "none" }
+            >
+                <img
+                    src={get_url("/assets/plus.svg".to_string())}
+                    style="height: 1.5rem; display: grid;"
+                    class="invert" 
+                />
+            </button>
             <button
                 on:click:target={move |_| if let Some(ref mut f) = on_remove { f(); }}
                 style="padding: 0;"
@@ -177,6 +240,10 @@ fn Match(
             { move || {
                 let food = food.clone();
                 let nutrients = nutrients.clone();
+                let swaps = match (modal_open.get(), &find_swaps) {
+                    (true, Some(f)) => f(),
+                    _ => vec![],
+                };
                 view!{
                     <Modal
                         title={ format!("{} {}", food.emoji.clone(), food.display_name.clone()) }
@@ -185,9 +252,27 @@ fn Match(
                     >
                         <h3><em> { food.name.clone() } </em></h3>
                         <p style="margin: 1rem 0">
-                            "Here is the nutritional composition for 100 grams of "{ food.display_name.clone() }:
+                            "Here is the nutritional composition for "{ format_float(food.grams) }" grams of "{ food.display_name.clone() }:
                         </p>
                         <NutrientTable nutrients={nutrients} nutrient_values={food.nutrients} />
+                        { (!swaps.is_empty()).then(|| view! {
+                            <p style="margin: 1rem 0"> "🔄 Healthier swaps:" </p>
+                            { swaps
+                                .into_iter()
+                                .map(|(swap, explanation)| view! {
+                                    <button
+                                        style="text-decoration: underline; text-align: left; padding: 0.25rem 0;"
+                                        on:click:target=move |_| {
+                                            if let Some(f) = on_swap { f(swap.clone()); }
+                                            set_modal_open.set(false);
+                                        }
+                                    >
+                                        { explanation }
+                                    </button>
+                                })
+                                .collect::<Vec<_>>()
+                            }
+                        }) }
                     </Modal>
                 }
             } }
@@ -199,8 +284,20 @@ fn Match(
 fn FoodSearch(
     set_selected_foods: WriteSignal<Vec<Food>>,
     data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    dietary_settings: Signal<DietarySettings>,
+    history: Signal<Vec<DayTotals>>,
+    search_settings: Signal<SearchSettings>,
 ) -> impl IntoView {
     let (search, set_search) = signal("".to_string());
+    // built once the foods have loaded rather than on every keystroke
+    let index = Memo::new(move |_| match data.read().as_deref() {
+        Some(Ok((_, foods))) => Some(build_index(foods)),
+        _ => None::<SearchIndex>,
+    });
+    let usage = Memo::new(move |_| Usage {
+        foods: food_usage(&history.get(), today()),
+        weight: search_settings.get().personalisation,
+    });
     view! {
         <div class="search-outer">
             <div class="search-container">
@@ -208,33 +305,94 @@ fn FoodSearch(
                     on:input:target=move |e| set_search.set(e.target().value())
                     prop:value={search}
                     placeholder="+ Search foods"
+                    title="Add filters like protein>5, sugar<5 or high:iron, per 100 g"
                     style="font-size: 1rem;"
                 />
                 <div class="search-options">
                     { move || {
-                        if search.read().len() == 0 {
+                        if search.read().is_empty() {
                             return vec![view!{}.into_any()];
                         }
-                        match data.read().as_deref() {
-                            Some(Ok((_, foods))) =>
-                                lookup_food(foods, search.get())
-                                    .iter()
-                                    .map(|f| {
-                                        let food = (*f).clone();
+                        match (data.read().as_deref(), index.read().as_ref()) {
+                            (Some(Ok((nutrients, foods))), Some(index)) => {
+                                let query = match parse_typed_filters(nutrients, &search.get()) {
+                                    Ok(query) => query,
+                                    Err(e) => return vec![view!{
+                                        <p style="font-size: 0.9rem; opacity: 0.8; padding: 0.5rem;"> { e } </p>
+                                    }.into_any()],
+                                };
+                                let settings = dietary_settings.get();
+                                let allowed = |f: &Food| {
+                                    (!settings.filter_search || is_allowed(f, &settings))
+                                        && query.filters.iter().all(|q| passes(q, f))
+                                };
+                                let groups = group_variants(
+                                    index,
+                                    foods,
+                                    search_query(index, foods, &query, allowed, &usage.read()),
+                                    allowed,
+                                );
+                                if groups.is_empty() {
+                                    return vec![view!{
+                                        <p style="font-size: 0.9rem; opacity: 0.8; padding: 0.5rem;"> No foods match </p>
+                                    }.into_any()];
+                                }
+                                // only the food picked is copied out of the data
+                                let pick = move |i: usize| {
+                                    if let Some(Ok((_, foods))) = data.read().as_deref() {
+                                        let food = foods[i].clone();
+                                        set_selected_foods.update(move |sf| sf.push(food));
+                                    }
+                                    set_search.set("".to_string());
+                                };
+                                groups
+                                    .into_iter()
+                                    .map(|g| {
+                                        let (i, f) = (g.best.id, &foods[g.best.id]);
                                         view! {
                                             <button
-                                                on:click:target=move |_| {
-                                                    let food = food.clone();
-                                                    set_selected_foods.update(move |sf| sf.push(food));
-                                                    set_search.set("".to_string());
-                                                }
+                                                on:click:target=move |_| pick(i)
                                                 style="font-size: 0.9rem; white-space: pre;"
                                             >
-                                                { f.emoji.clone() }"  "{ f.display_name.to_string() }
+                                                // one span so the bold parts don't become grid cells
+                                                <span>
+                                                    { f.emoji.clone() }"  "
+                                                    { highlight(&f.display_name, &g.best.indices)
+                                                        .into_iter()
+                                                        .map(|(text, matched)| match matched {
+                                                            true => view! { <b>{ text }</b> }.into_any(),
+                                                            false => view! { { text } }.into_any(),
+                                                        })
+                                                        .collect::<Vec<_>>()
+                                                    }
+                                                    { (!query.filters.is_empty()).then(|| view! {
+                                                        <span style="font-size: 0.8rem; opacity: 0.8;">
+                                                            "  "{ filtered_amounts(nutrients, &query, f) }" per 100 g"
+                                                        </span>
+                                                    }) }
+                                                </span>
                                             </button>
+                                            { (g.variants.len() > 1).then(|| view! {
+                                                <div style="display: flex; flex-wrap: wrap; gap: 0.25rem; padding: 0 1rem 0.5rem 2.5rem;">
+                                                    { g.variants
+                                                        .into_iter()
+                                                        .map(|(v, label)| view! {
+                                                            <button
+                                                                on:click:target=move |_| pick(v)
+                                                                style="font-size: 0.8rem; padding: 0.1rem 0.5rem; border: 1px solid var(--bg2);"
+                                                                style:background={ if v == i { "var(--bg2)" } else { "unset" } }
+                                                            >
+                                                                { if label.is_empty() { foods[v].display_name.clone() } else { label } }
+                                                            </button>
+                                                        })
+                                                        .collect::<Vec<_>>()
+                                                    }
+                                                </div>
+                                            }) }
                                         }.into_any()
                                     })
-                                    .collect::<Vec<_>>(),
+                                    .collect::<Vec<_>>()
+                            },
                             _ =>
                                 vec![view!{}.into_any()],
                         }
@@ -245,6 +403,12 @@ fn FoodSearch(
     }
 }
 
+// days since 1970-01-01 where the user is, so the day turns over at their midnight
+fn today() -> i64 {
+    let now = js_sys::Date::new_0();
+    ((now.get_time() - now.get_timezone_offset() * 60_000.) / 86_400_000.).floor() as i64
+}
+
 fn get_tasty_message(selected_foods: Vec<Food>) -> String {
     let seed = selected_foods
         .iter()
@@ -258,31 +422,154 @@ fn get_tasty_message(selected_foods: Vec<Food>) -> String {
     ][seed % 5].to_string()
 }
 
+// what the optimiser was last asked to work out, so that it only runs
+// when someone asks rather than whenever the page changes
+#[derive(Debug, Clone, PartialEq)]
+struct PlanRequest {
+    nutrients: Vec<Nutrient>,
+    nutrients_sum: HashMap<String, f32>,
+    effective_sum: HashMap<String, f32>,
+    dietary_settings: DietarySettings,
+    constraints: Constraints,
+}
+
+// the portions to eat, how many targets they reach and the day's totals
+// with them eaten
+type NextFoods = (Vec<(Food, f32)>, usize, HashMap<String, f32>);
+
 #[component]
 fn FoodReport(
     selected_foods: ReadSignal<Vec<Food>>,
+    set_selected_foods: WriteSignal<Vec<Food>>,
     data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    nutrients: Signal<Vec<Nutrient>>,
+    preset: Signal<Option<Preset>>,
+    dietary_settings: Signal<DietarySettings>,
+    recommend_settings: Signal<RecommendSettings>,
+    history: Signal<Vec<DayTotals>>,
+    averaging: Signal<AveragingSettings>,
 ) -> impl IntoView {
     let (modal_open, set_modal_open) = signal(false);
+    let (plan_open, set_plan_open) = signal(false);
+    let (objective, set_objective) = signal(Objective::Shortfall);
+    // energy to plan with when it isn't everything that is left
+    let (energy_budget_override, set_energy_budget_override) = signal(None::<f32>);
+    // set by an explicit click, so typing an energy budget or switching
+    // objective doesn't solve again until asked to
+    let (plan_request, set_plan_request) = signal(None::<PlanRequest>);
+    // solved once for each request, and not again as the page re-renders
+    let plan = Memo::new(move |_| {
+        let request = plan_request.get()?;
+        let data = data.read();
+        let Some(Ok((_, foods))) = data.as_deref() else {
+            return None;
+        };
+        Some(optimise_diet(
+            &request.nutrients,
+            &request.effective_sum,
+            &recommendable_foods(foods, &request.dietary_settings),
+            &request.constraints,
+        ).map(|o| -> NextFoods { (
+            o.portions
+                .iter()
+                .map(|p| (p.food.clone(), p.grams))
+                .collect::<Vec<(Food, f32)>>(),
+            targets_met(&request.nutrients, &o.totals),
+            // back from the averaged sums to what today would add up to
+            o.totals
+                .iter()
+                .map(|(n, x)| (n.clone(), x - request.effective_sum[n] + request.nutrients_sum[n]))
+                .collect::<HashMap<String, f32>>(),
+        ) }))
+    });
     view! {
         { move || {
-            if selected_foods.read().len() == 0 {
+            if selected_foods.read().is_empty() {
                 return view!{}.into_any();
             }
             match data.read().as_deref() {
-                Some(Ok((nutrients,foods))) => {
+                Some(Ok((_,foods))) => {
+                    let nutrients = nutrients.get();
                     let nutrients_sum = sum_nutrients(nutrients.clone(), selected_foods.get());                   
-                    let recommended_foods = recommend_foods(
+                    // nutrients judged over several days count what earlier
+                    // days were ahead or behind by
+                    let averaging = averaging.get();
+                    let effective_sum = effective_sum(&nutrients, &nutrients_sum, &history.get(), today(), &averaging);
+                    let averages = rolling_average(&nutrients, &nutrients_sum, &history.get(), today(), &averaging);
+                    let averaged_nutrients = nutrients
+                        .iter()
+                        .filter(|n| averages.contains_key(&n.name))
+                        .cloned()
+                        .collect::<Vec<Nutrient>>();
+                    let recommendations = recommend_portions(
                         nutrients.clone(),
                         &foods,
-                        nutrients_sum.clone(),
+                        effective_sum.clone(),
+                        &dietary_settings.get(),
+                        &recommend_settings.get(),
                     );
                     let (highest_nutrient, _) =
                         get_highest_and_lowest_nutrients(
-                            nutrients.clone(), nutrients_sum.clone(),
+                            nutrients.clone(), effective_sum.clone(),
                         );
+                    let eaten = selected_foods.get();
+                    let excesses = find_excesses(&nutrients, &eaten);
+                    let over_limits = excesses
+                        .iter()
+                        .map(|e| e.nutrient.display_name.clone())
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    // the biggest contributor to each excess, cut down to what
+                    // brings it back under or taken out altogether
+                    let cut_downs = excesses
+                        .iter()
+                        .map(|e| (
+                            explain_excess(e, &eaten),
+                            e.contributors.first().map(|&(i, amount)| (i, reduced_portion(e, &eaten[i], amount))),
+                        ))
+                        .collect::<Vec<(String, Option<(usize, f32)>)>>();
+                    let highlighted_nutrients = preset
+                        .get()
+                        .map_or(vec![], |p| {
+                            let highlights = preset_highlights(p);
+                            nutrients
+                                .iter()
+                                .filter(|n| highlights.contains(&n.name.as_str()))
+                                .cloned()
+                                .collect::<Vec<Nutrient>>()
+                        });
+                    // solving is only worth doing once someone asks
+                    let energy_budget = energy_budget_override
+                        .get()
+                        .unwrap_or(remaining_energy(&nutrients, &nutrients_sum));
+                    // counting carbs, what is eaten next is a meal and keeps to a meal's carbs
+                    let meal_carbs = preset.get().and_then(preset_carb_budget).map(|c| c.meal_g);
+                    let request = PlanRequest {
+                        nutrients: keep_to_carbs(nutrients.clone(), &effective_sum, meal_carbs),
+                        nutrients_sum: nutrients_sum.clone(),
+                        effective_sum: effective_sum.clone(),
+                        dietary_settings: dietary_settings.get(),
+                        constraints: Constraints {
+                            energy_budget_kcal: energy_budget,
+                            objective: objective.get(),
+                            ..Constraints::default()
+                        },
+                    };
+                    let requested = plan_request.get();
+                    let out_of_date = requested.as_ref() != Some(&request);
+                    let ask = move |objective: Objective| {
+                        let constraints = Constraints { objective, ..request.constraints.clone() };
+                        let request = PlanRequest { constraints, ..request.clone() };
+                        if plan_request.get_untracked().as_ref() != Some(&request) {
+                            set_plan_request.set(Some(request));
+                        }
+                    };
+                    let ask1 = ask.clone();
+                    let ask2 = ask.clone();
                     let nutrients1 = nutrients.clone();
+                    let nutrients2 = nutrients.clone();
                     let nutrients_sum1 = nutrients_sum.clone();
+                    let nutrients_sum2 = nutrients_sum.clone();
                     view! {
                         <button
                             style="white-space: pre-wrap; margin: 0 -1rem -0.75rem -1rem; font-size: 1rem;"
@@ -290,31 +577,202 @@ fn FoodReport(
                         >
                             <p> 
                                 { get_tasty_message(selected_foods.get()) }
-                                "! You have had a lot of "
-                                { highest_nutrient.display_name }" 😋 "
+                                { if excesses.is_empty() {
+                                    format!("! You have had a lot of {} 😋 ", highest_nutrient.display_name)
+                                } else {
+                                    format!("! Watch out for your {over_limits} though ⚠️ ")
+                                } }
                                 <span style="text-decoration: underline;">
                                     Click here
                                 </span>" to view your overall nutrient breakdown for today."
                             </p>
                         </button>
+                        { preset.get().map(|p| view! {
+                            <p> "Keeping an eye on these for "{ preset_name(p) }":" </p>
+                            <NutrientTable nutrients={highlighted_nutrients} nutrient_values={nutrients_sum2} />
+                        }) }
+                        { (!cut_downs.is_empty()).then(|| view! {
+                            <p> "You have gone over some limits, try cutting down:" </p>
+                            { cut_downs
+                                .into_iter()
+                                .map(|(explanation, cut)| view! {
+                                    <p style="font-size: 0.9rem;">
+                                        { explanation }" "
+                                        { cut.map(|(i, grams)| view! {
+                                            <button
+                                                style="text-decoration: underline; padding: 0;"
+                                                on:click:target=move |_| set_selected_foods.update(|sf| {
+                                                    if grams > 0. {
+                                                        sf[i] = portion_food(&sf[i], grams);
+                                                    } else {
+                                                        sf.remove(i);
+                                                    }
+                                                })
+                                            >
+                                                "✂️ Cut down"
+                                            </button>
+                                        }) }
+                                    </p>
+                                })
+                                .collect::<Vec<_>>()
+                            }
+                        }) }
                         <p>
                             Try eating some of these foods to balance your diet:
                         </p>
                         {
-                            recommended_foods
+                            recommendations
                                 .iter()
-                                .map(|f| {
-                                    let food = (*f).clone();
+                                .map(|r| {
+                                    let food = portion_food(r.food, r.grams);
+                                    let portion = food.clone();
+                                    let explanation = explain_recommendation(&nutrients, r);
+                                    // hovering shows which nutrients pushed the score up or down most
+                                    let breakdown = r.contributions
+                                        .iter()
+                                        .sorted_by_key(|(_, score)| -score.abs())
+                                        .take(6)
+                                        .filter_map(|(name, score)| nutrients
+                                            .iter()
+                                            .find(|n| &n.name == name)
+                                            .map(|n| format!("{} {:+}", n.display_name, score))
+                                        )
+                                        .collect::<Vec<String>>()
+                                        .join("\n");
                                     view! {
                                         <Match
                                             food={food}
                                             nutrients={nutrients.clone()}
                                             on_remove={None::<fn() -> ()>}
+                                            on_add={Some(move ||
+                                                set_selected_foods.update(|sf| sf.push(portion.clone()))
+                                            )}
+                                            find_swaps={None::<fn() -> Vec<(Food, String)>>}
+                                            on_swap={None::<fn(Food) -> ()>}
                                         />
+                                        <p
+                                            style="font-size: 0.8rem; opacity: 0.8; margin: -0.1rem 0 0.25rem 1rem;"
+                                            title={breakdown}
+                                        >
+                                            { explanation }
+                                        </p>
                                     }.into_any()
                                 })
                                 .collect::<Vec<_>>()
                         }
+                        <div style="display: flex; gap: 1rem;">
+                            <button
+                                style="text-decoration: underline; padding: 0;"
+                                on:click:target=move |_| {
+                                    set_objective.set(Objective::Shortfall);
+                                    ask1(Objective::Shortfall);
+                                    set_plan_open.set(true);
+                                }
+                            >
+                                "🧮 Work out exactly what to eat"
+                            </button>
+                            <button
+                                style="text-decoration: underline; padding: 0;"
+                                on:click:target=move |_| {
+                                    set_objective.set(Objective::TargetsMet);
+                                    ask2(Objective::TargetsMet);
+                                    set_plan_open.set(true);
+                                }
+                            >
+                                "🍽️ Plan my dinner"
+                            </button>
+                        </div>
+                        <Modal
+                            title="🧮 What To Eat Next".to_string()
+                            open={plan_open.get()}
+                            close={move || {
+                                set_plan_open.set(false);
+                                set_energy_budget_override.set(None);
+                                set_plan_request.set(None);
+                            }}
+                        >
+                            <div style="display: flex; gap: 0.5rem; margin: 0.5rem 0;">
+                                { [Objective::Shortfall, Objective::TargetsMet]
+                                    .map(|o| view! {
+                                        <button
+                                            style:background=move || if objective.get() == o { "var(--bg2)" } else { "unset" }
+                                            on:click:target=move |_| set_objective.set(o)
+                                        >
+                                            { objective_name(o) }
+                                        </button>
+                                    })
+                                }
+                            </div>
+                            <div style="display: grid; grid-template-columns: auto 6rem; gap: 0.5rem; align-items: center;">
+                                <ProfileNumberInput
+                                    label="Energy to spend (kcal)"
+                                    value={energy_budget.round()}
+                                    on_change={move |x| set_energy_budget_override.set(Some(x.max(0.)))}
+                                />
+                            </div>
+                            { out_of_date.then(|| view! {
+                                <button
+                                    style="text-decoration: underline; justify-self: start; padding: 0;"
+                                    on:click:target=move |_| ask(objective.get_untracked())
+                                >
+                                    { if requested.is_some() { "🧮 Plan again" } else { "🧮 Plan" } }
+                                </button>
+                            }) }
+                            { match (plan.get(), requested) {
+                                (Some(Err(e)), _) => view! {
+                                    <p style="margin: 1rem 0">
+                                        "Sorry, this couldn't be worked out ("{ e }"). Try again, perhaps
+                                        with less energy to spend."
+                                    </p>
+                                }.into_any(),
+                                (Some(Ok((portions, met, totals))), Some(requested)) if !portions.is_empty() => {
+                                    let targets = nutrients2
+                                        .iter()
+                                        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+                                        .count();
+                                    let added = portions.clone();
+                                    view! {
+                                        <p style="margin: 1rem 0">
+                                            { match requested.constraints.objective {
+                                                Objective::Shortfall => "These amounts get you as close as possible to your RIs within ",
+                                                Objective::TargetsMet => "These amounts reach as many of your RIs as possible within ",
+                                            } }
+                                            { format!("{:.0}", requested.constraints.energy_budget_kcal) }" kcal, without going over any limits"
+                                            { meal_carbs.map(|g| format!(" or {}g of carbohydrate for the meal", format_float(g))) }":"
+                                        </p>
+                                        { portions
+                                            .iter()
+                                            .map(|(f, grams)| view! {
+                                                <p> <b>{ format_float(*grams) }" g"</b>" of "{ f.emoji.clone() }" "{ f.display_name.clone() } </p>
+                                            })
+                                            .collect::<Vec<_>>()
+                                        }
+                                        <button
+                                            style="text-decoration: underline; justify-self: start; padding: 0; margin-top: 0.5rem;"
+                                            on:click:target=move |_| {
+                                                set_selected_foods.update(|sf| sf.extend(
+                                                    added.iter().map(|(f, grams)| portion_food(f, *grams))
+                                                ));
+                                                set_plan_open.set(false);
+                                            }
+                                        >
+                                            "➕ Add these to today"
+                                        </button>
+                                        <p style="margin: 1rem 0">
+                                            "Here is how your day would look, reaching "{ met }" of "{ targets }" targets:"
+                                        </p>
+                                        <NutrientTable nutrients={nutrients2.clone()} nutrient_values={totals} />
+                                    }.into_any()
+                                },
+                                (Some(Ok(_)), _) => view! {
+                                    <p style="margin: 1rem 0">
+                                        "There isn't anything that would help within that much energy
+                                        without going over a limit."
+                                    </p>
+                                }.into_any(),
+                                _ => view!{}.into_any(),
+                            } }
+                        </Modal>
                         <Modal
                             title="⚖️  Nutrition Breakdown".to_string()
                             open={modal_open.get()}
@@ -323,9 +781,17 @@ fn FoodReport(
                             <div style="display: grid; gap: 0.75rem;">
                                 <p style="margin: 1rem 0"> 
                                     This shows the combined breakdown of the nutrients you 
-                                    have eaten today, assuming you ate around 100 grams of
-                                    each selected food. </p>
+                                    have eaten today, counting 100 grams of each food you
+                                    searched for and the suggested amount of each food you
+                                    added from the recommendations. </p>
                                 <NutrientTable nutrients={nutrients1} nutrient_values={nutrients_sum1} />
+                                { (!averaged_nutrients.is_empty()).then(|| view! {
+                                    <p style="margin: 1rem 0">
+                                        "These are judged on your average over the last "{ averaging.days }
+                                        " days rather than today alone, as the body stores them:"
+                                    </p>
+                                    <NutrientTable nutrients={averaged_nutrients} nutrient_values={averages} />
+                                }) }
                             </div>
                         </Modal>
                     }.into_any()
@@ -337,16 +803,95 @@ fn FoodReport(
     }
 }
 
+#[component]
+fn RichFoods(
+    rich_nutrient: ReadSignal<Option<Nutrient>>,
+    set_rich_nutrient: WriteSignal<Option<Nutrient>>,
+    set_selected_foods: WriteSignal<Vec<Food>>,
+    data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    nutrients: Signal<Vec<Nutrient>>,
+    dietary_settings: Signal<DietarySettings>,
+) -> impl IntoView {
+    let (basis, set_basis) = signal(Basis::Per100g);
+    view! {
+        { move || {
+            let Some(nutrient) = rich_nutrient.get() else {
+                return view!{}.into_any();
+            };
+            match data.read().as_deref() {
+                Some(Ok((_, foods))) => {
+                    let rich_foods = rich_in(
+                        foods,
+                        &nutrient.name,
+                        basis.get(),
+                        &dietary_settings.get(),
+                        10,
+                    );
+                    let rich_food_views = rich_foods
+                        .iter()
+                        .map(|f| {
+                            let amount = nutrient_amount(f, &nutrient.name, basis.get());
+                            // the basis only ranks the foods, what's added is a usual portion
+                            let food = portion_food(f, typical_portion(f));
+                            let portion = food.clone();
+                            view! {
+                                <Match
+                                    food={food}
+                                    nutrients={nutrients.get()}
+                                    on_remove={None::<fn() -> ()>}
+                                    on_add={Some(move ||
+                                        set_selected_foods.update(|sf| sf.push(portion.clone()))
+                                    )}
+                                    find_swaps={None::<fn() -> Vec<(Food, String)>>}
+                                    on_swap={None::<fn(Food) -> ()>}
+                                />
+                                <p style="font-size: 0.8rem; opacity: 0.8; margin: -0.1rem 0 0.25rem 1rem;">
+                                    { format_float(amount) }{ nutrient.units.clone() }" "{ basis_name(basis.get()) }
+                                </p>
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    view! {
+                        <Modal
+                            title={ format!("🔍 Foods Rich In {}", nutrient.display_name) }
+                            open={true}
+                            close={move || set_rich_nutrient.set(None)}
+                        >
+                            <div style="display: flex; gap: 0.5rem; margin: 0.5rem 0;">
+                                { BASES
+                                    .iter()
+                                    .map(|b| {
+                                        let b = *b;
+                                        view! {
+                                            <button
+                                                style:background=move || if basis.get() == b { "var(--bg2)" } else { "unset" }
+                                                on:click:target=move |_| set_basis.set(b)
+                                            >
+                                                { basis_name(b) }
+                                            </button>
+                                        }
+                                    })
+                                    .collect::<Vec<_>>()
+                                }
+                            </div>
+                            { rich_food_views }
+                        </Modal>
+                    }.into_any()
+                },
+                _ => view!{}.into_any(),
+            }
+        }}
+    }
+}
+
 #[component]
 fn SelectedFoods(
     selected_foods: ReadSignal<Vec<Food>>,
     set_selected_foods: WriteSignal<Vec<Food>>,
     data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    nutrients: Signal<Vec<Nutrient>>,
+    dietary_settings: Signal<DietarySettings>,
 ) -> impl IntoView {
-    let nutrients = move || match data.read().as_deref() {
-        Some(Ok((nutrients,_))) => nutrients.clone(),
-        _ => Vec::<Nutrient>::new(),
-    };
     view! {
         { move || {
             selected_foods
@@ -355,15 +900,34 @@ fn SelectedFoods(
                 .enumerate()
                 .map(|(i,f)| {
                     let food = f.clone();
+                    let swapped = f.clone();
+                    let find_swaps = move || match data.read().as_deref() {
+                        Some(Ok((_, foods))) => {
+                            let nutrients = nutrients.get();
+                            find_substitutions(&nutrients, &swapped, foods, &dietary_settings.get(), 3)
+                                .iter()
+                                .map(|s| (
+                                    portion_food(s.food, s.grams),
+                                    explain_substitution(&nutrients, &swapped, s),
+                                ))
+                                .collect::<Vec<(Food, String)>>()
+                        },
+                        _ => vec![],
+                    };
                     view! {
                         <Match
                             food={food}
-                            nutrients={nutrients()}
+                            nutrients={nutrients.get()}
                             on_remove={Some(move ||
                                 set_selected_foods.update(|sf| {
                                     (*sf).remove(i);
                                 })
                             )}
+                            on_add={None::<fn() -> ()>}
+                            find_swaps={Some(find_swaps)}
+                            on_swap={Some(move |swap: Food|
+                                set_selected_foods.update(|sf| sf[i] = swap)
+                            )}
                         />
                     }
                 })
@@ -373,24 +937,921 @@ fn SelectedFoods(
 }
 
 
+#[component]
+fn ProfileNumberInput(
+    label: &'static str,
+    value: f32,
+    mut on_change: impl FnMut(f32) -> () + 'static,
+) -> impl IntoView {
+    view! {
+        <label> { label } </label>
+        <input
+            type="number"
+            prop:value={value}
+            on:change:target=move |e| {
+                if let Ok(x) = e.target().value().parse::<f32>() {
+                    on_change(x);
+                }
+            }
+        />
+    }
+}
+
+#[component]
+fn ProfileForm(
+    profile: Signal<Option<Profile>>,
+    set_profile: WriteSignal<Option<Profile>>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    let (draft, set_draft) = signal(profile.get_untracked().unwrap_or_default());
+    view! {
+        <button
+            on:click:target=move |_| {
+                set_draft.set(profile.get().unwrap_or_default());
+                set_modal_open.set(true);
+            }
+        >
+            "👤 Profile"
+        </button>
+        { move || view! {
+            <Modal
+                title="👤 Profile".to_string()
+                open={modal_open.get()}
+                close={move || set_modal_open.set(false)}
+            >
+                <p style="margin: 1rem 0">
+                    "Your energy goal is worked out from your basal metabolic rate
+                    (Mifflin-St Jeor equation) and how active you are."
+                </p>
+                <div style="display: grid; grid-template-columns: max-content 1fr; gap: 0.5rem; align-items: center;">
+                    <ProfileNumberInput
+                        label="Weight (kg)"
+                        value={draft.get().weight_kg}
+                        on_change={move |x| set_draft.update(|p| p.weight_kg = x.clamp(20., 300.))}
+                    />
+                    <ProfileNumberInput
+                        label="Height (cm)"
+                        value={draft.get().height_cm}
+                        on_change={move |x| set_draft.update(|p| p.height_cm = x.clamp(100., 250.))}
+                    />
+                    <ProfileNumberInput
+                        label="Age (years)"
+                        value={draft.get().age_years}
+                        on_change={move |x| set_draft.update(|p| p.age_years = x.clamp(18., 100.))}
+                    />
+                    <label> Sex </label>
+                    <select
+                        on:change:target=move |e| {
+                            let sex = match e.target().value().as_str() {
+                                "male" => Sex::Male,
+                                _ => Sex::Female,
+                            };
+                            set_draft.update(|p| p.sex = sex);
+                        }
+                    >
+                        <option value="female" selected={draft.get().sex == Sex::Female}> Female </option>
+                        <option value="male" selected={draft.get().sex == Sex::Male}> Male </option>
+                    </select>
+                    <label> Activity </label>
+                    <select
+                        on:change:target=move |e| {
+                            if let Ok(i) = e.target().value().parse::<usize>() {
+                                set_draft.update(|p| p.activity = ACTIVITY_LEVELS[i]);
+                            }
+                        }
+                    >
+                        { ACTIVITY_LEVELS
+                            .iter()
+                            .enumerate()
+                            .map(|(i, a)| view! {
+                                <option value={i} selected={draft.get().activity == *a}>
+                                    { activity_description(*a) }
+                                </option>
+                            })
+                            .collect::<Vec<_>>()
+                        }
+                    </select>
+                    <label> Scale macros </label>
+                    <input
+                        type="checkbox"
+                        style="appearance: auto; justify-self: start;"
+                        prop:checked={draft.get().scale_macros}
+                        on:change:target=move |e| {
+                            let checked = e.target().checked();
+                            set_draft.update(|p| p.scale_macros = checked);
+                        }
+                    />
+                </div>
+                <p style="margin: 1rem 0">
+                    "Basal metabolic rate: "{ format!("{:.0}", basal_metabolic_rate(&draft.get())) }"kcal"
+                    <br />
+                    "Total daily energy expenditure: "{ format!("{:.0}", total_energy_expenditure(&draft.get())) }"kcal"
+                </p>
+                <div style="display: flex; gap: 0.5rem;">
+                    <button
+                        style="border: 1px solid var(--fg);"
+                        on:click:target=move |_| {
+                            set_profile.set(Some(draft.get()));
+                            set_modal_open.set(false);
+                        }
+                    >
+                        "Use this energy goal"
+                    </button>
+                    <button
+                        on:click:target=move |_| {
+                            set_profile.set(None);
+                            set_modal_open.set(false);
+                        }
+                    >
+                        "Reset to default RIs"
+                    </button>
+                </div>
+            </Modal>
+        } }
+    }
+}
+
+#[component]
+fn GoalInput(
+    value: Option<f32>,
+    placeholder: String,
+    mut on_change: impl FnMut(Option<f32>) -> () + 'static,
+) -> impl IntoView {
+    view! {
+        <input
+            type="number"
+            style="width: 6rem; text-align: right;"
+            placeholder={placeholder}
+            prop:value={value.map_or("".to_string(), format_float)}
+            on:change:target=move |e| on_change(e.target().value().trim().parse::<f32>().ok())
+        />
+    }
+}
+
+fn update_goal(
+    set_goals: WriteSignal<HashMap<String, NutrientGoal>>,
+    name: String,
+    f: impl FnOnce(&mut NutrientGoal) -> (),
+) -> () {
+    set_goals.update(|goals| {
+        let goal = goals.entry(name.clone()).or_default();
+        f(goal);
+        if goal.target.is_none() && goal.limit.is_none() {
+            goals.remove(&name);
+        }
+    })
+}
+
+#[component]
+fn GoalsEditor(
+    default_nutrients: Signal<Vec<Nutrient>>,
+    goals: Signal<HashMap<String, NutrientGoal>>,
+    set_goals: WriteSignal<HashMap<String, NutrientGoal>>,
+    preset: Signal<Option<Preset>>,
+    set_preset: WriteSignal<Option<Preset>>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    view! {
+        <button on:click:target=move |_| set_modal_open.set(true)>
+            "🎯 Goals"
+        </button>
+        { move || view! {
+            <Modal
+                title="🎯 Goals".to_string()
+                open={modal_open.get()}
+                close={move || set_modal_open.set(false)}
+            >
+                <div style="display: flex; gap: 0.5rem; align-items: center; margin-top: 1rem;">
+                    <label> Preset </label>
+                    <select
+                        on:change:target=move |e| {
+                            set_preset.set(
+                                e.target().value().parse::<usize>().ok().map(|i| PRESETS[i])
+                            );
+                        }
+                    >
+                        <option value="none" selected={preset.get().is_none()}> "None" </option>
+                        { PRESETS
+                            .iter()
+                            .enumerate()
+                            .map(|(i, p)| view! {
+                                <option value={i} selected={preset.get() == Some(*p)}>
+                                    { preset_name(*p) }
+                                </option>
+                            })
+                            .collect::<Vec<_>>()
+                        }
+                    </select>
+                </div>
+                { preset.get().map(|p| view! {
+                    <p> <em> { preset_description(p) } </em> </p>
+                }) }
+                <p style="margin: 1rem 0">
+                    "Set your own targets and limits for any nutrient. Leave a box
+                    empty to use the recommended intake."
+                </p>
+                <div
+                    style="display: grid; grid-template-columns: 1fr max-content max-content; column-gap: 0.5rem; align-items: center;"
+                >
+                    <p style="font-weight: bold;"> Nutrient </p>
+                    <p style="font-weight: bold;"> Target </p>
+                    <p style="font-weight: bold;"> Max </p>
+                    { default_nutrients
+                        .get()
+                        .into_iter()
+                        .map(|n| {
+                            let goal = goals.read().get(&n.name).copied().unwrap_or_default();
+                            let target_placeholder = match n.kind {
+                                NutrientKind::Target => format_float(n.recommended_intake),
+                                _ => "-".to_string(),
+                            };
+                            let limit_placeholder = match n.kind {
+                                NutrientKind::Limit => Some(n.recommended_intake),
+                                _ => n.upper_limit,
+                            }.map_or("-".to_string(), format_float);
+                            let name_target = n.name.clone();
+                            let name_limit = n.name.clone();
+                            view! {
+                                <p> { n.display_name.clone() }" ("{ n.units.clone() }")" </p>
+                                <GoalInput
+                                    value={goal.target}
+                                    placeholder={target_placeholder}
+                                    on_change={move |x| update_goal(set_goals, name_target.clone(), |g| g.target = x)}
+                                />
+                                <GoalInput
+                                    value={goal.limit}
+                                    placeholder={limit_placeholder}
+                                    on_change={move |x| update_goal(set_goals, name_limit.clone(), |g| g.limit = x)}
+                                />
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                    }
+                </div>
+                <button
+                    style="justify-self: start; margin-top: 0.5rem;"
+                    on:click:target=move |_| set_goals.set(HashMap::new())
+                >
+                    "Reset all goals"
+                </button>
+            </Modal>
+        } }
+    }
+}
+
+#[component]
+fn Checkbox(
+    label: String,
+    checked: bool,
+    mut on_change: impl FnMut(bool) -> () + 'static,
+) -> impl IntoView {
+    view! {
+        <label style="display: flex; gap: 0.5rem; align-items: center;">
+            <input
+                type="checkbox"
+                style="appearance: auto;"
+                prop:checked={checked}
+                on:change:target=move |e| on_change(e.target().checked())
+            />
+            { label }
+        </label>
+    }
+}
+
+fn toggle<T: PartialEq>(items: &mut Vec<T>, item: T, on: bool) -> () {
+    items.retain(|i| *i != item);
+    if on {
+        items.push(item);
+    }
+}
+
+#[component]
+fn DietForm(
+    dietary_settings: Signal<DietarySettings>,
+    set_dietary_settings: WriteSignal<DietarySettings>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    view! {
+        <button on:click:target=move |_| set_modal_open.set(true)>
+            "🥗 Diet"
+        </button>
+        { move || {
+            let settings = dietary_settings.get();
+            view! {
+                <Modal
+                    title="🥗 Diet".to_string()
+                    open={modal_open.get()}
+                    close={move || set_modal_open.set(false)}
+                >
+                    <p style="margin: 1rem 0">
+                        "Recommendations will only include foods which fit your diet
+                        and don't contain anything you avoid. Foods are tagged from
+                        their names, so always check the label!"
+                    </p>
+                    <h3> Diets </h3>
+                    { DIETS
+                        .iter()
+                        .map(|d: &Diet| {
+                            let diet = *d;
+                            view! {
+                                <Checkbox
+                                    label={diet_name(diet).to_string()}
+                                    checked={settings.diets.contains(&diet)}
+                                    on_change={move |on| set_dietary_settings.update(|s| toggle(&mut s.diets, diet, on))}
+                                />
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                    }
+                    <h3 style="margin-top: 0.5rem;"> Avoid </h3>
+                    { ALLERGENS
+                        .iter()
+                        .map(|t: &Tag| {
+                            let tag = *t;
+                            view! {
+                                <Checkbox
+                                    label={tag_name(tag).to_string()}
+                                    checked={settings.allergens.contains(&tag)}
+                                    on_change={move |on| set_dietary_settings.update(|s| toggle(&mut s.allergens, tag, on))}
+                                />
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                    }
+                    <h3 style="margin-top: 0.5rem;"> Search </h3>
+                    <Checkbox
+                        label={"Hide foods which don't fit from search results".to_string()}
+                        checked={settings.filter_search}
+                        on_change={move |on| set_dietary_settings.update(|s| s.filter_search = on)}
+                    />
+                </Modal>
+            }
+        } }
+    }
+}
+
+#[component]
+fn SuggestionsForm(
+    recommend_settings: Signal<RecommendSettings>,
+    set_recommend_settings: WriteSignal<RecommendSettings>,
+    nutrients: Signal<Vec<Nutrient>>,
+    averaging: Signal<AveragingSettings>,
+    set_averaging: WriteSignal<AveragingSettings>,
+    search_settings: Signal<SearchSettings>,
+    set_search_settings: WriteSignal<SearchSettings>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    view! {
+        <button on:click:target=move |_| set_modal_open.set(true)>
+            "💡 Suggestions"
+        </button>
+        { move || {
+            let settings = recommend_settings.get();
+            view! {
+                <Modal
+                    title="💡 Suggestions".to_string()
+                    open={modal_open.get()}
+                    close={move || set_modal_open.set(false)}
+                >
+                    <p style="margin: 1rem 0">
+                        "Choose how many foods to suggest at a time and how they are
+                        scored. With variety on, foods which are much like one already
+                        suggested are pushed down the list."
+                    </p>
+                    <div style="display: grid; grid-template-columns: 1fr 8rem; gap: 0.5rem; align-items: center;">
+                        <ProfileNumberInput
+                            label="Number of suggestions"
+                            value={settings.count as f32}
+                            on_change={move |x| set_recommend_settings.update(|s| s.count = x.clamp(1., 12.) as usize)}
+                        />
+                    </div>
+                    <h3 style="margin-top: 0.5rem;"> Scoring </h3>
+                    <select
+                        on:change:target=move |e| {
+                            if let Ok(i) = e.target().value().parse::<usize>() {
+                                set_recommend_settings.update(|s| s.scorer = SCORERS[i]);
+                            }
+                        }
+                    >
+                        { SCORERS
+                            .iter()
+                            .enumerate()
+                            .map(|(i, k)| view! {
+                                <option value={i} selected={settings.scorer == *k}>
+                                    { scorer_name(*k) }
+                                </option>
+                            })
+                            .collect::<Vec<_>>()
+                        }
+                    </select>
+                    <p style="font-size: 0.9rem; opacity: 0.8;"> { scorer_description(settings.scorer) } </p>
+                    <Checkbox
+                        label={"Prefer a variety of foods".to_string()}
+                        checked={settings.diversity > 0.}
+                        on_change={move |on| set_recommend_settings.update(|s| s.diversity = if on { 1. } else { 0. })}
+                    />
+                    <h3 style="margin-top: 0.5rem;"> Averaging </h3>
+                    <p style="font-size: 0.9rem; opacity: 0.8;">
+                        "Some nutrients are stored by the body, so it's enough to get them
+                        on average over a few days. Ticked nutrients are judged on your
+                        average over this many days, including today."
+                    </p>
+                    <div style="display: grid; grid-template-columns: 1fr 8rem; gap: 0.5rem; align-items: center;">
+                        <ProfileNumberInput
+                            label="Days to average over"
+                            value={averaging.get().days as f32}
+                            on_change={move |x| set_averaging.update(|a| a.days = x.clamp(1., 28.) as usize)}
+                        />
+                    </div>
+                    { nutrients
+                        .get()
+                        .into_iter()
+                        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+                        .map(|n| {
+                            let name = n.name.clone();
+                            view! {
+                                <Checkbox
+                                    label={n.display_name}
+                                    checked={averaging.get().averaged.contains(&n.name)}
+                                    on_change={move |on| set_averaging.update(|a| toggle(&mut a.averaged, name.clone(), on))}
+                                />
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                    }
+                    <h3 style="margin-top: 0.5rem;"> Search </h3>
+                    <p style="font-size: 0.9rem; opacity: 0.8;">
+                        "Foods you log often and lately come higher in search results. At
+                        100%, a food you have every day scores twice what its name alone
+                        would, at 0% results are ranked by name only."
+                    </p>
+                    <div style="display: grid; grid-template-columns: 1fr 8rem; gap: 0.5rem; align-items: center;">
+                        <ProfileNumberInput
+                            label="Weight of foods you usually eat (%)"
+                            value={(search_settings.get().personalisation * 100.).round()}
+                            on_change={move |x| set_search_settings.update(|s| s.personalisation = x.clamp(0., 200.) / 100.)}
+                        />
+                    </div>
+                </Modal>
+            }
+        } }
+    }
+}
+
+#[component]
+fn FoodQuery(
+    data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    set_selected_foods: WriteSignal<Vec<Food>>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    let (text, set_text) = signal("".to_string());
+    view! {
+        <button on:click:target=move |_| set_modal_open.set(true)>
+            "🧮 Query"
+        </button>
+        { move || view! {
+            <Modal
+                title="🧮 Query Foods".to_string()
+                open={modal_open.get()}
+                close={move || set_modal_open.set(false)}
+            >
+                <p style="margin: 1rem 0">
+                    "Pick out foods with a condition on their nutrients per 100 g, using
+                    + - * / ( ), comparisons, and, or and not, then sort and limit them."
+                </p>
+                <input
+                    on:input:target=move |e| set_text.set(e.target().value())
+                    prop:value={text}
+                    placeholder="protein_g / energy_kcal * 100 > 10 and sodium_mg < 200 order by fibre_g desc limit 20"
+                    style="font-size: 0.9rem; font-family: monospace;"
+                />
+                { move || {
+                    let text = text.get();
+                    if text.trim().is_empty() {
+                        return view!{}.into_any();
+                    }
+                    let data = data.read();
+                    let Some(Ok((nutrients, foods))) = data.as_deref() else {
+                        return view!{}.into_any();
+                    };
+                    let query = match parse_food_query(nutrients, &text) {
+                        Ok(query) => query,
+                        Err(e) => return view! {
+                            <pre style="font-size: 0.8rem; overflow-x: auto; margin: 0.5rem 0;">
+                                { explain_error(&text, &e) }
+                            </pre>
+                        }.into_any(),
+                    };
+                    let results = run_query(&query, foods);
+                    if results.is_empty() {
+                        return view! {
+                            <p style="font-size: 0.9rem; opacity: 0.8; margin: 0.5rem 0;"> No foods match </p>
+                        }.into_any();
+                    }
+                    results
+                        .into_iter()
+                        .map(|(i, value)| {
+                            let food = foods[i].clone();
+                            view! {
+                                <div style="display: flex; justify-content: space-between; align-items: baseline; gap: 0.5rem;">
+                                    <p style="font-size: 0.9rem;">
+                                        { food.emoji.clone() }" "{ food.display_name.clone() }
+                                        { value.map(|v| view! {
+                                            <span style="font-size: 0.8rem; opacity: 0.8;">"  "{ format_float(v) }</span>
+                                        }) }
+                                    </p>
+                                    <button
+                                        style="text-decoration: underline; padding: 0;"
+                                        on:click:target=move |_| set_selected_foods.update(|sf| sf.push(food.clone()))
+                                    >
+                                        "+ Add"
+                                    </button>
+                                </div>
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .into_any()
+                }}
+            </Modal>
+        } }
+    }
+}
+
+#[component]
+fn MealPlanner(
+    data: LocalResource<Result<(Vec<Nutrient>, Vec<Food>)>>,
+    nutrients: Signal<Vec<Nutrient>>,
+    dietary_settings: Signal<DietarySettings>,
+    set_selected_foods: WriteSignal<Vec<Food>>,
+    meal_plan: Signal<Option<MealPlan>>,
+    set_meal_plan: WriteSignal<Option<MealPlan>>,
+    plan_settings: Signal<PlanSettings>,
+    set_plan_settings: WriteSignal<PlanSettings>,
+    preset: Signal<Option<Preset>>,
+) -> impl IntoView {
+    let (modal_open, set_modal_open) = signal(false);
+    let (export_open, set_export_open) = signal(false);
+    let (shopping_open, set_shopping_open) = signal(false);
+    let (list_format, set_list_format) = signal(ListFormat::Text);
+    // meals planned so far out of how many, while a plan is being made
+    let (progress, set_progress) = signal(None::<(usize, usize)>);
+    // worked out once for each plan, and only while the planner is open
+    let opened_plan = move || if modal_open.get() { meal_plan.get() } else { None };
+    let average = Memo::new(move |_| match (opened_plan(), data.read().as_deref()) {
+        (Some(p), Some(Ok((_, foods)))) => Some(daily_average(&nutrients.get(), foods, &p)),
+        _ => None,
+    });
+    let text = Memo::new(move |_| match (opened_plan(), data.read().as_deref()) {
+        (Some(p), Some(Ok((_, foods)))) => plan_text(foods, &p),
+        _ => "".to_string(),
+    });
+    let shopping = Memo::new(move |_| match (opened_plan(), data.read().as_deref()) {
+        (Some(p), Some(Ok((_, foods)))) => shopping_list(foods, &p),
+        _ => vec![],
+    });
+    // each day's portions, with each meal written out
+    let days = Memo::new(move |_| match (opened_plan(), data.read().as_deref()) {
+        (Some(p), Some(Ok((_, foods)))) => p.days
+            .iter()
+            .map(|day| (
+                day_foods(foods, day),
+                day
+                    .iter()
+                    .map(|m| (
+                        m.meal,
+                        // shown next to the meal when counting carbs
+                        preset.get().and_then(preset_carb_budget).map(|_| meal_carbohydrate(foods, m)),
+                        m.foods
+                            .iter()
+                            .map(|(name, grams)| format!(
+                                "{} g of {}",
+                                format_float(*grams),
+                                foods
+                                    .iter()
+                                    .find(|f| &f.name == name)
+                                    .map_or(name.clone(), |f| format!("{} {}", f.emoji, f.display_name)),
+                            ))
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                    ))
+                    .collect::<Vec<(Meal, Option<f32>, String)>>(),
+            ))
+            .collect::<Vec<(Vec<Food>, Vec<(Meal, Option<f32>, String)>)>>(),
+        _ => vec![],
+    });
+    // planned a meal at a time, giving the page a chance to show how far
+    // it has got in between
+    let make_plan = move || {
+        if progress.get_untracked().is_some() {
+            return;
+        }
+        let foods = match data.read_untracked().as_deref() {
+            Some(Ok((_, foods))) => foods.clone(),
+            _ => return,
+        };
+        let nutrients = nutrients.get_untracked();
+        let settings = dietary_settings.get_untracked();
+        let plan_settings = plan_settings.get_untracked();
+        let carbs = preset.get_untracked().and_then(preset_carb_budget);
+        spawn_local(async move {
+            let mut planner = start_plan(&nutrients, &foods, &settings, &plan_settings, carbs, Some(PLAN_TIME_LIMIT));
+            set_progress.set(Some(plan_progress(&planner)));
+            TimeoutFuture::new(0).await;
+            while plan_next_meal(&mut planner) {
+                set_progress.set(Some(plan_progress(&planner)));
+                TimeoutFuture::new(0).await;
+            }
+            set_meal_plan.set(Some(finish_plan(planner)));
+            set_progress.set(None);
+        });
+    };
+    view! {
+        <button on:click:target=move |_| set_modal_open.set(true)>
+            "📅 Meal plan"
+        </button>
+        { move || {
+            let settings = plan_settings.get();
+            let nutrients = nutrients.get();
+            let plan = meal_plan.get();
+            let shopping_text = export_list(&shopping.get(), list_format.get());
+            view! {
+                <Modal
+                    title="📅 Meal Plan".to_string()
+                    open={modal_open.get()}
+                    close={move || set_modal_open.set(false)}
+                >
+                    <p style="margin: 1rem 0">
+                        "Plan breakfast, lunch, dinner and snacks for the week ahead. The
+                        plan aims for your RIs on average over the week within the energy
+                        you give it, sticks to your diet and allergens, and doesn't have
+                        any one food too often."
+                    </p>
+                    <div style="display: grid; grid-template-columns: 1fr 8rem; gap: 0.5rem; align-items: center;">
+                        <ProfileNumberInput
+                            label="Energy per day (kcal)"
+                            value={plan_energy(&nutrients, &settings).round()}
+                            on_change={move |x| set_plan_settings.update(|s| s.daily_energy_kcal = Some(x.max(0.)))}
+                        />
+                        { settings.daily_energy_kcal.is_some().then(|| view! {
+                            <button
+                                style="text-decoration: underline; justify-self: start; padding: 0; grid-column: 1 / -1;"
+                                on:click:target=move |_| set_plan_settings.update(|s| s.daily_energy_kcal = None)
+                            >
+                                "↩️ Use my energy RI"
+                            </button>
+                        }) }
+                        <ProfileNumberInput
+                            label="Foods per meal"
+                            value={settings.foods_per_meal as f32}
+                            on_change={move |x| set_plan_settings.update(|s| s.foods_per_meal = x.clamp(1., 6.) as usize)}
+                        />
+                        <ProfileNumberInput
+                            label="Most times to have any one food"
+                            value={settings.max_repeats as f32}
+                            on_change={move |x| set_plan_settings.update(|s| s.max_repeats = x.clamp(1., 28.) as usize)}
+                        />
+                    </div>
+                    <div style="display: flex; gap: 1rem; margin: 1rem 0;">
+                        <button
+                            style="text-decoration: underline; padding: 0;"
+                            disabled={progress.get().is_some()}
+                            on:click:target=move |_| make_plan()
+                        >
+                            { match progress.get() {
+                                Some((planned, total)) => format!("⏳ Planning meal {} of {total}", (planned + 1).min(total)),
+                                None if plan.is_some() => "🔄 Make a new plan".to_string(),
+                                None => "✨ Make a plan".to_string(),
+                            } }
+                        </button>
+                        { plan.is_some().then(|| view! {
+                            <button
+                                style="text-decoration: underline; padding: 0;"
+                                on:click:target=move |_| set_export_open.update(|o| *o = !*o)
+                            >
+                                "📋 Export"
+                            </button>
+                            <button
+                                style="text-decoration: underline; padding: 0;"
+                                on:click:target=move |_| set_shopping_open.update(|o| *o = !*o)
+                            >
+                                "🛒 Shopping list"
+                            </button>
+                        }) }
+                    </div>
+                    { export_open.get().then(|| view! {
+                        <textarea
+                            readonly
+                            style="width: 100%; height: 12rem; font-size: 0.8rem;"
+                            prop:value={text.get()}
+                        />
+                    }) }
+                    { shopping_open.get().then(|| view! {
+                        <h3> "🛒 Shopping List" </h3>
+                        { shopping
+                            .get()
+                            .iter()
+                            .map(|(group, items)| view! {
+                                <p style="margin-top: 0.5rem;"><b>{ group.clone() }</b></p>
+                                { items
+                                    .iter()
+                                    .map(|i| view! {
+                                        <p style="font-size: 0.9rem;">
+                                            { i.emoji.clone() }" "{ format_amount(i.grams) }" of "{ i.display_name.clone() }
+                                        </p>
+                                    })
+                                    .collect::<Vec<_>>()
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                        }
+                        <div style="display: flex; gap: 0.5rem; margin: 0.5rem 0;">
+                            { LIST_FORMATS
+                                .map(|f| view! {
+                                    <button
+                                        style:background=move || if list_format.get() == f { "var(--bg2)" } else { "unset" }
+                                        on:click:target=move |_| set_list_format.set(f)
+                                    >
+                                        { list_format_name(f) }
+                                    </button>
+                                })
+                            }
+                        </div>
+                        <textarea
+                            readonly
+                            style="width: 100%; height: 12rem; font-size: 0.8rem;"
+                            prop:value={shopping_text}
+                        />
+                    }) }
+                    { days
+                        .get()
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, (eaten, meals))| view! {
+                            <div style="display: flex; justify-content: space-between; align-items: baseline; margin-top: 1rem;">
+                                <h3> "Day "{ i + 1 } </h3>
+                                <button
+                                    style="text-decoration: underline; padding: 0;"
+                                    on:click:target=move |_| {
+                                        set_selected_foods.set(eaten.clone());
+                                        set_modal_open.set(false);
+                                    }
+                                >
+                                    "📥 Load into today"
+                                </button>
+                            </div>
+                            { meals
+                                .into_iter()
+                                .map(|(meal, carbs, foods)| view! {
+                                    <p style="font-size: 0.9rem;">
+                                        <b>
+                                            { meal_name(meal) }
+                                            { carbs.map(|g| format!(" ({:.0} g carbs)", g)) }
+                                            ": "
+                                        </b>
+                                        { foods }
+                                    </p>
+                                })
+                                .collect::<Vec<_>>()
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                    }
+                    { average.get().map(|a| view! {
+                        <p style="margin: 1rem 0"> "On an average day of the plan you would have:" </p>
+                        <NutrientTable nutrients={nutrients.clone()} nutrient_values={a} />
+                    }) }
+                </Modal>
+            }
+        } }
+    }
+}
+
 #[component]
 fn Foods() -> impl IntoView {
     let (selected_foods, set_selected_foods) = signal(Vec::<Food>::new());
     let data = LocalResource::new(move || get_data());
+    let (profile, set_profile, _) =
+        use_local_storage::<Option<Profile>, JsonSerdeCodec>("balance-profile");
+    let (goals, set_goals, _) =
+        use_local_storage::<HashMap<String, NutrientGoal>, JsonSerdeCodec>("balance-goals");
+    let (preset, set_preset, _) =
+        use_local_storage::<Option<Preset>, JsonSerdeCodec>("balance-preset");
+    let (dietary_settings, set_dietary_settings, _) =
+        use_local_storage::<DietarySettings, JsonSerdeCodec>("balance-diet");
+    let (recommend_settings, set_recommend_settings, _) =
+        use_local_storage::<RecommendSettings, JsonSerdeCodec>("balance-recommend");
+    let (meal_plan, set_meal_plan, _) =
+        use_local_storage::<Option<MealPlan>, JsonSerdeCodec>("balance-plan");
+    let (plan_settings, set_plan_settings, _) =
+        use_local_storage::<PlanSettings, JsonSerdeCodec>("balance-plan-settings");
+    let (history, set_history, _) =
+        use_local_storage::<Vec<DayTotals>, JsonSerdeCodec>("balance-history");
+    let (averaging, set_averaging, _) =
+        use_local_storage::<AveragingSettings, JsonSerdeCodec>("balance-averaging");
+    let (search_settings, set_search_settings, _) =
+        use_local_storage::<SearchSettings, JsonSerdeCodec>("balance-search");
+    let default_nutrients = Signal::derive(move || match data.read().as_deref() {
+        Some(Ok((nutrients,_))) => apply_preset(
+            apply_profile(nutrients.clone(), &profile.get()),
+            &preset.get(),
+        ),
+        _ => Vec::<Nutrient>::new(),
+    });
+    let nutrients = Signal::derive(move ||
+        apply_custom_goals(default_nutrients.get(), &goals.get())
+    );
+    // today's totals are kept so later days can be averaged with them. an
+    // empty list is usually just a fresh page load so it only clears today
+    // once something has been added and then taken off again
+    Effect::new(move |had_foods: Option<bool>| {
+        let foods = selected_foods.get();
+        let nutrients = nutrients.get();
+        let had_foods = had_foods.unwrap_or(false);
+        if !foods.is_empty() && !nutrients.is_empty() {
+            let names = foods.iter().map(|f| f.name.clone()).collect();
+            set_history.update(|h| record_day(h, today(), sum_nutrients(nutrients, foods), names));
+            true
+        } else if foods.is_empty() && had_foods {
+            set_history.update(|h| forget_day(h, today()));
+            false
+        } else {
+            had_foods
+        }
+    });
+    let (rich_nutrient, set_rich_nutrient) = signal(None::<Nutrient>);
+    provide_context(FindRichFoods(set_rich_nutrient));
 
     view! {
+        <div style="display: flex; gap: 0.5rem; margin: 0 -1rem;">
+            <ProfileForm profile={profile} set_profile={set_profile} />
+            <GoalsEditor
+                default_nutrients={default_nutrients}
+                goals={goals}
+                set_goals={set_goals}
+                preset={preset}
+                set_preset={set_preset}
+            />
+            <DietForm
+                dietary_settings={dietary_settings}
+                set_dietary_settings={set_dietary_settings}
+            />
+            <SuggestionsForm
+                recommend_settings={recommend_settings}
+                set_recommend_settings={set_recommend_settings}
+                nutrients={nutrients}
+                averaging={averaging}
+                set_averaging={set_averaging}
+                search_settings={search_settings}
+                set_search_settings={set_search_settings}
+            />
+            <MealPlanner
+                data={data}
+                nutrients={nutrients}
+                dietary_settings={dietary_settings}
+                set_selected_foods={set_selected_foods}
+                meal_plan={meal_plan}
+                set_meal_plan={set_meal_plan}
+                plan_settings={plan_settings}
+                set_plan_settings={set_plan_settings}
+                preset={preset}
+            />
+            <FoodQuery
+                data={data}
+                set_selected_foods={set_selected_foods}
+            />
+        </div>
         <SelectedFoods
             selected_foods={selected_foods}
             set_selected_foods={set_selected_foods}
             data={data}
+            nutrients={nutrients}
+            dietary_settings={dietary_settings}
         />
         <FoodSearch
             set_selected_foods={set_selected_foods}
             data={data}
+            dietary_settings={dietary_settings}
+            history={history}
+            search_settings={search_settings}
         />
         <FoodReport
             selected_foods={selected_foods}
+            set_selected_foods={set_selected_foods}
+            data={data}
+            nutrients={nutrients}
+            preset={preset}
+            dietary_settings={dietary_settings}
+            recommend_settings={recommend_settings}
+            history={history}
+            averaging={averaging}
+        />
+        <RichFoods
+            rich_nutrient={rich_nutrient}
+            set_rich_nutrient={set_rich_nutrient}
+            set_selected_foods={set_selected_foods}
             data={data}
+            nutrients={nutrients}
+            dietary_settings={dietary_settings}
         />
     }
 }
@@ -1,7 +1,24 @@
 use std::collections::HashMap;
 use itertools::Itertools;
-use fuzzy_matcher::FuzzyMatcher;
-use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Serialize, Deserialize};
+
+pub mod goals;
+pub mod diet;
+pub mod rules;
+pub mod optimise;
+pub mod scoring;
+pub mod plan;
+pub mod shopping;
+pub mod history;
+pub mod search;
+pub mod filters;
+pub mod query;
+// the command line is a native binary and has no place in the app
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+use diet::{Tag, DietarySettings, is_allowed};
+use rules::{classify, default_rules};
+use scoring::{Scorer, ScorerKind, scorer};
 
 
 pub fn format_float(x: f32) -> String {
@@ -13,25 +30,115 @@ pub fn format_float(x: f32) -> String {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NutrientKind {
+    // something to reach, recommended_intake is the goal
+    Target,
+    // something to stay under, recommended_intake is the maximum
+    Limit,
+    // shown in the breakdown but not scored
+    Informational,
+}
+
+fn parse_nutrient_kind(s: &str) -> NutrientKind {
+    match s {
+        "target" => NutrientKind::Target,
+        "limit" => NutrientKind::Limit,
+        _ => NutrientKind::Informational,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Nutrient {
     pub name: String,
     pub display_name: String,
     pub abbreviation: String,
     pub units: String,
     pub recommended_intake: f32,
+    pub kind: NutrientKind,
+    // tolerable upper intake level, above which a target becomes harmful
+    pub upper_limit: Option<f32>,
 }
 
+// energy comes in both kcal and kJ, and only needs counting once
+pub fn is_duplicate(nutrient: &Nutrient) -> bool {
+    nutrient.name == "energy_kJ"
+}
 
-#[derive(Debug, Clone)]
+pub fn is_over_limit(nutrient: &Nutrient, value: f32) -> bool {
+    match nutrient.kind {
+        NutrientKind::Limit =>
+            nutrient.recommended_intake > 0.1 && value > nutrient.recommended_intake,
+        _ => nutrient.upper_limit.is_some_and(|ul| value > ul),
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Food {
     pub name: String,
     pub display_name: String,
     recommend: bool,
     pub emoji: String,
+    pub group: String,
+    pub tags: Vec<Tag>,
+    // nutrients are for this many grams, 100 for foods from the dataset
+    pub grams: f32,
     pub nutrients: HashMap<String, f32>,
 }
 
+// spices and the like often have no energy value in cofid, so rather than
+// treating them as free fall back to the atwater factors
+pub fn energy_kcal(food: &Food) -> f32 {
+    match food.nutrients["energy_kcal"] {
+        e if e > 0. => e,
+        _ => 4. * food.nutrients["protein_g"]
+            + 9. * food.nutrients["fat_g"]
+            + 3.75 * food.nutrients["carbohydrate_g"],
+    }
+}
+
+// so that foods with next to no energy don't score or rank without bound
+const MIN_ENERGY_KCAL: f32 = 25.;
+
+// what to multiply a food's nutrients by to get them per 100 kcal
+pub fn per_100_kcal(food: &Food) -> f32 {
+    100. / energy_kcal(food).max(MIN_ENERGY_KCAL)
+}
+
+// every food goes through here so that the tagging rules apply to it
+pub fn new_food(
+    name: String,
+    display_name: String,
+    emoji: String,
+    recommend: bool,
+    nutrients: HashMap<String, f32>,
+) -> Food {
+    let classification = classify(default_rules(), &name, &display_name, &emoji);
+    Food {
+        name,
+        display_name,
+        recommend,
+        emoji: classification.emoji,
+        group: classification.group,
+        tags: classification.tags,
+        grams: 100.,
+        nutrients,
+    }
+}
+
+pub fn portion_food(food: &Food, grams: f32) -> Food {
+    let scale = grams / food.grams;
+    Food {
+        grams,
+        nutrients: food.nutrients
+            .iter()
+            .map(|(n, x)| (n.clone(), x * scale))
+            .collect::<HashMap<String, f32>>(),
+        ..food.clone()
+    }
+}
+
 fn make_food(
     record: csv::StringRecord,
     nutrients: Vec<Nutrient>,
@@ -63,19 +170,24 @@ fn make_food(
                 n.name.to_string(),
                 f,
             ),
+            // unknown values are guessed generously for targets but
+            // limits shouldn't be pushed over by foods we know nothing about
             Err(_) => (
                 n.name.to_string(),
-                n.recommended_intake / 5.,
+                match n.kind {
+                    NutrientKind::Target => n.recommended_intake / 5.,
+                    _ => 0.,
+                },
             ),
         })
         .collect::<HashMap<String, f32>>();
-    Food {
-        name: name,
-        display_name: display_name,
-        recommend: recommend,
-        emoji: emoji,
-        nutrients: nutrient_values,
-    }
+    new_food(
+        name,
+        display_name,
+        emoji,
+        recommend,
+        nutrient_values,
+    )
 }
 
 fn get_nutrients(
@@ -84,7 +196,7 @@ fn get_nutrients(
     let mut nutrients = Vec::<Nutrient>::new();
     let mut headers: Vec<Vec<String>> = reader
         .records()
-        .take(5)
+        .take(7)
         .map(|r| r
             .expect("cofid.csv is error free")
             .into_iter()
@@ -101,12 +213,19 @@ fn get_nutrients(
                 0.,
                 |s| s
             );
+        let kind = parse_nutrient_kind(&headers[5].remove(0));
+        let upper_limit: Option<f32> = headers[6]
+            .remove(0)
+            .parse()
+            .ok();
         let new_nutrient = Nutrient {
             name: headers[0].remove(0),
             display_name: headers[3].remove(0),
             abbreviation: headers[2].remove(0),
             units: headers[1].remove(0),
-            recommended_intake: recommended_intake,
+            recommended_intake,
+            kind,
+            upper_limit,
         };
         nutrients.push(new_nutrient);
     }
@@ -133,17 +252,24 @@ pub fn get_foods(csv: String) -> (Vec<Nutrient>, Vec<Food>) {
 pub fn lookup_food(
     foods: &Vec<Food>, search: String
 ) -> Vec<Food> {
-    let matcher = SkimMatcherV2::default();
-    let search = search.trim().to_lowercase();
-    foods
-        .iter()
-        .k_largest_by_key(
-            20,
-            |f| matcher
-                .fuzzy_match(&f.display_name, &search)
-                .unwrap_or(0) * 100 - f.display_name.len() as i64
-        )
-        .map(|f| f.clone())
+    lookup(foods.iter(), search)
+}
+
+pub fn lookup_allowed_food(
+    foods: &[Food], search: String, settings: &DietarySettings
+) -> Vec<Food> {
+    lookup(foods.iter().filter(|f| is_allowed(f, settings)), search)
+}
+
+// a one off search ranked by name alone, the app searches with an index
+// and what the user usually eats instead. every food which matches at all
+// is kept, so it is only empty when nothing does
+fn lookup<'a>(
+    foods: impl Iterator<Item = &'a Food>, search: String
+) -> Vec<Food> {
+    search::rank_all(foods, |f| *f, &search, &search::Usage::default())
+        .into_iter()
+        .map(|(f, _)| f.clone())
         .collect::<Vec<Food>>()
 }
 
@@ -165,62 +291,608 @@ pub fn sum_nutrients(
         .collect::<HashMap<String, f32>>()
 }
 
-fn balance_score(
+// what each nutrient adds to the balance score, leaving out the ones which
+// don't matter and counting energy once
+pub fn score_contributions(
     nutrients: &Vec<Nutrient>,
     food: &Food,
-    nutrients_sum: &HashMap<String, f32>
-) -> i64 {
+    nutrients_sum: &HashMap<String, f32>,
+    scorer: &dyn Scorer,
+) -> Vec<(String, i64)> {
     nutrients
         .iter()
-        .filter(|n| n.recommended_intake > 0.1)
-        .map(|n| ((( 1000. * food.nutrients[&n.name] / n.recommended_intake ) * ( 1. - 4. * nutrients_sum[&n.name] / n.recommended_intake )) as i64).min(1000).max(-1000))
+        .filter(|n| n.recommended_intake > 0.1 && !is_duplicate(n))
+        .map(|n| (
+            n.name.clone(),
+            scorer.nutrient_score(n, food, nutrients_sum[&n.name]),
+        ))
+        .filter(|(_, score)| *score != 0)
+        .collect::<Vec<(String, i64)>>()
+}
+
+fn food_score(
+    nutrients: &Vec<Nutrient>,
+    food: &Food,
+    nutrients_sum: &HashMap<String, f32>,
+    scorer: &dyn Scorer,
+) -> i64 {
+    score_contributions(nutrients, food, nutrients_sum, scorer)
+        .iter()
+        .map(|(_, score)| score)
         .sum()
 }
 
-pub fn recommend_foods(
-    nutrients: Vec<Nutrient>,
-    foods: &Vec<Food>,
-    nutrients_sum: HashMap<String, f32>,
-) -> Vec<&Food> {
+// the foods it makes sense to suggest to this person
+pub fn recommendable_foods<'a>(
+    foods: &'a [Food],
+    settings: &DietarySettings,
+) -> Vec<&'a Food> {
     foods
         .iter()
-        .filter(|f| f.recommend)
+        .filter(|f| f.recommend && is_allowed(f, settings))
+        .collect::<Vec<&Food>>()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecommendSettings {
+    pub count: usize,
+    // how hard to push away from foods like ones already suggested, 0 is off
+    pub diversity: f32,
+    pub scorer: ScorerKind,
+}
+
+impl Default for RecommendSettings {
+    fn default() -> Self {
+        RecommendSettings {
+            count: 3,
+            diversity: 1.,
+            scorer: ScorerKind::Balanced,
+        }
+    }
+}
+
+// the part of the cofid name before the first comma, "liver" for "Liver, lamb, fried"
+fn base_name(food: &Food) -> String {
+    food.name
+        .split(',')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+}
+
+// cosine similarity of the foods' targets as fractions of the RI,
+// and foods with the same base name are treated as the same thing
+fn similarity(nutrients: &[Nutrient], a: &Food, b: &Food) -> f32 {
+    if base_name(a) == base_name(b) {
+        return 1.;
+    }
+    let (dot, a_square, b_square) = nutrients
+        .iter()
+        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+        .fold((0., 0., 0.), |(dot, a_square, b_square), n| {
+            let x = a.nutrients[&n.name] / a.grams / n.recommended_intake;
+            let y = b.nutrients[&n.name] / b.grams / n.recommended_intake;
+            (dot + x * y, a_square + x * x, b_square + y * y)
+        });
+    if a_square > 0. && b_square > 0. {
+        dot / (a_square * b_square).sqrt()
+    } else {
+        0.
+    }
+}
+
+// only the best few are worth checking for near-duplicates
+const DIVERSITY_CANDIDATES_PER_SUGGESTION: usize = 10;
+
+// picks the best food one at a time, marking the rest down by how much
+// they resemble what has already been picked
+pub fn recommend_foods<'a>(
+    nutrients: Vec<Nutrient>,
+    foods: &'a [Food],
+    nutrients_sum: HashMap<String, f32>,
+    settings: &DietarySettings,
+    recommend_settings: &RecommendSettings,
+) -> Vec<&'a Food> {
+    let mut candidates = recommendable_foods(foods, settings)
+        .into_iter()
+        .map(|f| (f, food_score(&nutrients, f, &nutrients_sum, scorer(recommend_settings.scorer))))
         .k_largest_by_key(
-            3,
-            |f| balance_score(&nutrients, &f, &nutrients_sum)
+            recommend_settings.count * DIVERSITY_CANDIDATES_PER_SUGGESTION,
+            |(_, score)| *score
         )
+        .collect::<Vec<(&Food, i64)>>();
+    let mut chosen = Vec::<&Food>::new();
+    while chosen.len() < recommend_settings.count && !candidates.is_empty() {
+        let (best, _) = candidates
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (f, score))| {
+                let resemblance = chosen
+                    .iter()
+                    .map(|c| similarity(&nutrients, f, c))
+                    .fold(0., f32::max);
+                score - (recommend_settings.diversity * resemblance * score.abs() as f32) as i64
+            })
+            .expect("candidates is nonempty");
+        chosen.push(candidates.remove(best).0);
+    }
+    chosen
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Basis {
+    Per100g,
+    Per100kcal,
+    PerPortion,
+}
+
+pub const BASES: [Basis; 3] = [
+    Basis::Per100g,
+    Basis::Per100kcal,
+    Basis::PerPortion,
+];
+
+pub fn basis_name(basis: Basis) -> &'static str {
+    match basis {
+        Basis::Per100g => "per 100 g",
+        Basis::Per100kcal => "per 100 kcal",
+        Basis::PerPortion => "per portion",
+    }
+}
+
+// a rough everyday serving for each group in rules.csv
+pub fn typical_portion(food: &Food) -> f32 {
+    match food.group.as_str() {
+        "Drinks" => 250.,
+        "Dishes" => 300.,
+        "Sweets & desserts" => 60.,
+        "Beans & pulses" => 80.,
+        "Nuts & seeds" => 30.,
+        "Fats & oils" => 10.,
+        "Fish & seafood" => 140.,
+        "Meat" => 120.,
+        "Eggs" => 60.,
+        "Dairy" => 125.,
+        "Cereals & bread" => 75.,
+        "Fruit" => 80.,
+        "Vegetables" => 80.,
+        "Herbs, spices & sauces" => 5.,
+        _ => 100.,
+    }
+}
+
+// how many grams of the food the basis stands for
+pub fn basis_grams(food: &Food, basis: Basis) -> f32 {
+    match basis {
+        Basis::Per100g => 100.,
+        Basis::Per100kcal => food.grams * per_100_kcal(food),
+        Basis::PerPortion => typical_portion(food),
+    }
+}
+
+pub fn nutrient_amount(food: &Food, nutrient: &str, basis: Basis) -> f32 {
+    food.nutrients[nutrient] / food.grams * basis_grams(food, basis)
+}
+
+// the foods with the most of one nutrient, from those it makes sense to suggest
+pub fn rich_in<'a>(
+    foods: &'a [Food],
+    nutrient: &str,
+    basis: Basis,
+    settings: &DietarySettings,
+    count: usize,
+) -> Vec<&'a Food> {
+    recommendable_foods(foods, settings)
+        .into_iter()
+        .sorted_by(|a, b| nutrient_amount(b, nutrient, basis).total_cmp(&nutrient_amount(a, nutrient, basis)))
+        .take(count)
         .collect::<Vec<&Food>>()
 }
 
+const PORTION_STEP_G: f32 = 25.;
+const SMALL_PORTION_STEP_G: f32 = 5.;
+const MAX_PORTION_G: f32 = 300.;
+
+#[derive(Debug, Clone)]
+pub struct Recommendation<'a> {
+    pub food: &'a Food,
+    pub grams: f32,
+    // what each nutrient of the portion adds to its score
+    pub contributions: Vec<(String, i64)>,
+    // share of what is still missing of each target the portion makes up,
+    // biggest first
+    pub fills: Vec<(String, f32)>,
+}
+
+// the most of a food that doesn't push any limit further over
+fn max_portion(
+    nutrients: &[Nutrient],
+    food: &Food,
+    nutrients_sum: &HashMap<String, f32>,
+) -> f32 {
+    nutrients
+        .iter()
+        .filter(|n| !is_duplicate(n))
+        .filter_map(|n| {
+            let limit = match n.kind {
+                NutrientKind::Limit if n.recommended_intake > 0.1 => Some(n.recommended_intake),
+                NutrientKind::Target => n.upper_limit,
+                _ => None,
+            }?;
+            let per_gram = food.nutrients[&n.name] / food.grams;
+            (per_gram > 0.).then(|| (limit - nutrients_sum[&n.name]).max(0.) / per_gram)
+        })
+        .fold(MAX_PORTION_G, f32::min)
+}
+
+// how much of the remaining targets some grams of a food would fill
+fn deficit_filled(
+    nutrients: &[Nutrient],
+    food: &Food,
+    nutrients_sum: &HashMap<String, f32>,
+    grams: f32,
+) -> f32 {
+    nutrients
+        .iter()
+        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+        .map(|n| {
+            let deficit = (n.recommended_intake - nutrients_sum[&n.name]).max(0.);
+            (food.nutrients[&n.name] * grams / food.grams).min(deficit) / n.recommended_intake
+        })
+        .sum()
+}
+
+// grows the portion a step at a time while each step still does at least
+// half as much good as the first, never going past the limits, and 0 when
+// there's no room for even a small step
+pub fn suggest_portion(
+    nutrients: &[Nutrient],
+    food: &Food,
+    nutrients_sum: &HashMap<String, f32>,
+) -> f32 {
+    let most = max_portion(nutrients, food, nutrients_sum);
+    // a nibble of something very salty can still be worth suggesting
+    if most < PORTION_STEP_G {
+        return (most / SMALL_PORTION_STEP_G).floor() * SMALL_PORTION_STEP_G;
+    }
+    let filled = |grams: f32| deficit_filled(nutrients, food, nutrients_sum, grams);
+    let first_step = filled(PORTION_STEP_G);
+    let mut grams = PORTION_STEP_G;
+    while grams + PORTION_STEP_G <= most
+        && filled(grams + PORTION_STEP_G) - filled(grams) > first_step / 2. {
+        grams += PORTION_STEP_G;
+    }
+    grams
+}
+
+fn deficits_filled(
+    nutrients: &[Nutrient],
+    food: &Food,
+    nutrients_sum: &HashMap<String, f32>,
+    grams: f32,
+) -> Vec<(String, f32)> {
+    nutrients
+        .iter()
+        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+        .filter_map(|n| {
+            let deficit = n.recommended_intake - nutrients_sum[&n.name];
+            let value = food.nutrients[&n.name] * grams / food.grams;
+            (deficit > 0. && value > 0.).then(|| (n.name.clone(), (value / deficit).min(1.)))
+        })
+        .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+        .collect::<Vec<(String, f32)>>()
+}
+
+// "Vitamin C" reads better as "vitamin C" mid-sentence, but "AOAC fibre" should stay as it is
+fn mid_sentence(display_name: &str) -> String {
+    let mut chars = display_name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(first), Some(second)) if second.is_lowercase() =>
+            format!("{}{}", first.to_lowercase(), &display_name[first.len_utf8()..]),
+        _ => display_name.to_string(),
+    }
+}
+
+const EXPLAINED_NUTRIENTS: usize = 3;
+const EXPLAINED_MIN_SHARE: f32 = 0.05;
+
+// e.g. "adds 45% of your missing iron and 30% of folate"
+pub fn explain_recommendation(
+    nutrients: &[Nutrient],
+    recommendation: &Recommendation,
+) -> String {
+    let parts = recommendation.fills
+        .iter()
+        .filter(|(_, share)| *share >= EXPLAINED_MIN_SHARE)
+        .take(EXPLAINED_NUTRIENTS)
+        .filter_map(|(name, share)| nutrients
+            .iter()
+            .find(|n| &n.name == name)
+            .map(|n| (share * 100., mid_sentence(&n.display_name)))
+        )
+        .enumerate()
+        .map(|(i, (percentage, name))| match i {
+            0 => format!("{percentage:.0}% of your missing {name}"),
+            _ => format!("{percentage:.0}% of {name}"),
+        })
+        .collect::<Vec<String>>();
+    match parts.len() {
+        0 => "".to_string(),
+        _ => {
+            let (last, rest) = parts.split_last().expect("parts is nonempty");
+            let list = match rest.len() {
+                0 => last.clone(),
+                _ => format!("{} and {}", rest.join(", "), last),
+            };
+            format!("adds {list}")
+        },
+    }
+}
+
+pub fn recommend_portions<'a>(
+    nutrients: Vec<Nutrient>,
+    foods: &'a [Food],
+    nutrients_sum: HashMap<String, f32>,
+    settings: &DietarySettings,
+    recommend_settings: &RecommendSettings,
+) -> Vec<Recommendation<'a>> {
+    // some of the picks may have no room left under a limit, so twice as
+    // many are picked to leave enough once those are dropped
+    let spare = RecommendSettings { count: recommend_settings.count * 2, ..recommend_settings.clone() };
+    recommend_foods(nutrients.clone(), foods, nutrients_sum.clone(), settings, &spare)
+        .into_iter()
+        .map(|f| (f, suggest_portion(&nutrients, f, &nutrients_sum)))
+        .filter(|(_, grams)| *grams > 0.)
+        .take(recommend_settings.count)
+        .map(|(f, grams)| Recommendation {
+            food: f,
+            grams,
+            contributions: score_contributions(
+                &nutrients,
+                &portion_food(f, grams),
+                &nutrients_sum,
+                scorer(recommend_settings.scorer),
+            ),
+            fills: deficits_filled(&nutrients, f, &nutrients_sum, grams),
+        })
+        .collect::<Vec<Recommendation>>()
+}
+
 pub fn get_highest_and_lowest_nutrients(
     nutrients: Vec<Nutrient>,
     nutrient_values: HashMap<String, f32>
 ) -> (Nutrient, Nutrient) {
     let rank_nutrient = move |n: &&Nutrient| ( nutrient_values[&n.name] / n.recommended_intake * 1000. ) as usize;
+    // having a lot of a limit nutrient is nothing to celebrate, that is
+    // left to find_excesses
     (
         nutrients
             .iter()
-            .filter(|n| n.recommended_intake > 0.1)
+            .filter(|n| n.recommended_intake > 0.1 && n.kind != NutrientKind::Limit)
             .max_by_key(&rank_nutrient)
             .expect("nutrients is nonempty")
             .clone(),
         nutrients
             .iter()
-            .filter(|n| n.recommended_intake > 0.1)
+            .filter(|n| n.recommended_intake > 0.1 && n.kind != NutrientKind::Limit)
             .min_by_key(&rank_nutrient)
             .expect("nutrients is nonempty")
             .clone(),
     )
 }
 
+#[derive(Debug, Clone)]
+pub struct Excess {
+    pub nutrient: Nutrient,
+    pub total: f32,
+    // the limit or upper limit which has been gone over
+    pub limit: f32,
+    // indices into the eaten foods with how much of the nutrient each gave,
+    // biggest first
+    pub contributors: Vec<(usize, f32)>,
+}
+
+// the limits which have been gone over, the furthest over first
+pub fn find_excesses(
+    nutrients: &[Nutrient],
+    eaten: &[Food],
+) -> Vec<Excess> {
+    let nutrients_sum = sum_nutrients(nutrients.to_vec(), eaten.to_vec());
+    nutrients
+        .iter()
+        .filter(|n| !is_duplicate(n) && is_over_limit(n, nutrients_sum[&n.name]))
+        .map(|n| Excess {
+            nutrient: n.clone(),
+            total: nutrients_sum[&n.name],
+            limit: match n.kind {
+                NutrientKind::Limit => n.recommended_intake,
+                _ => n.upper_limit.expect("is_over_limit checked there is one"),
+            },
+            contributors: eaten
+                .iter()
+                .map(|f| f.nutrients[&n.name])
+                .enumerate()
+                .filter(|(_, amount)| *amount > 0.)
+                .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+                .collect(),
+        })
+        .sorted_by(|a, b| (b.total / b.limit).total_cmp(&(a.total / a.limit)))
+        .collect::<Vec<Excess>>()
+}
+
+// how much of a food would bring the total back down to the limit, in
+// small steps so it is easy to weigh out, 0 if it should go altogether
+pub fn reduced_portion(excess: &Excess, food: &Food, amount: f32) -> f32 {
+    let over = excess.total - excess.limit;
+    let grams = food.grams * (1. - over / amount).max(0.);
+    (grams / SMALL_PORTION_STEP_G).floor() * SMALL_PORTION_STEP_G
+}
+
+// e.g. "You have had 150% of your sodium limit, 60% of it from English
+// Cheddar. Try 75 g of it instead of 150 g."
+pub fn explain_excess(excess: &Excess, eaten: &[Food]) -> String {
+    let over = format!(
+        "You have had {:.0}% of your {} limit",
+        excess.total / excess.limit * 100.,
+        mid_sentence(&excess.nutrient.display_name),
+    );
+    let Some(&(i, amount)) = excess.contributors.first() else {
+        return format!("{over}.");
+    };
+    let food = &eaten[i];
+    let grams = reduced_portion(excess, food, amount);
+    let advice = if grams > 0. {
+        format!("Try {} g of it instead of {} g.", format_float(grams), format_float(food.grams))
+    } else if amount < excess.total - excess.limit {
+        "Try leaving it out, and cutting down on the rest.".to_string()
+    } else {
+        "Try leaving it out.".to_string()
+    };
+    format!(
+        "{over}, {:.0}% of it from {}. {advice}",
+        amount / excess.total * 100.,
+        food.display_name,
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct Substitution<'a> {
+    pub food: &'a Food,
+    // the same amount as the food it replaces
+    pub grams: f32,
+    // the biggest improvements per nutrient, more of a target or less of a limit
+    pub changes: Vec<(String, f32)>,
+}
+
+// a food is similar enough to swap for if it is in the same group and
+// either the same kind of thing or about as filling
+const SIMILAR_ENERGY_RATIO: f32 = 1.25;
+// how much healthier a swap has to be to be worth suggesting
+const MIN_SUBSTITUTION_GAIN: f32 = 0.05;
+const SUBSTITUTION_CHANGES: usize = 3;
+
+fn is_similar(a: &Food, b: &Food) -> bool {
+    let (a_energy, b_energy) = (energy_kcal(a) / a.grams, energy_kcal(b) / b.grams);
+    a.group == b.group
+        && (base_name(a) == base_name(b)
+            || a_energy.max(b_energy) <= a_energy.min(b_energy) * SIMILAR_ENERGY_RATIO)
+}
+
+// targets up to their RI count for a food and limits against it, each as
+// a fraction of the RI for some grams of the food
+fn healthiness(nutrients: &[Nutrient], food: &Food, grams: f32) -> f32 {
+    nutrients
+        .iter()
+        .filter(|n| n.recommended_intake > 0.1 && !is_duplicate(n))
+        .map(|n| {
+            let share = food.nutrients[&n.name] * grams / food.grams / n.recommended_intake;
+            match n.kind {
+                NutrientKind::Target => share.min(1.),
+                NutrientKind::Limit => -share,
+                NutrientKind::Informational => 0.,
+            }
+        })
+        .sum()
+}
+
+// changes are shown to a tenth below 10 and whole above it
+fn round_change(change: f32) -> f32 {
+    if change.abs() < 10. { (change * 10.).round() / 10. } else { change.round() }
+}
+
+// the biggest improvements per nutrient from eating some grams of f instead
+// of food, leaving out any too small to show
+fn substitution_changes(nutrients: &[Nutrient], food: &Food, f: &Food, grams: f32) -> Vec<(String, f32)> {
+    nutrients
+        .iter()
+        .filter(|n| n.recommended_intake > 0.1 && !is_duplicate(n))
+        .filter_map(|n| {
+            let change = (f.nutrients[&n.name] / f.grams - food.nutrients[&n.name] / food.grams) * grams;
+            let better = match n.kind {
+                NutrientKind::Target => change > 0.,
+                NutrientKind::Limit => change < 0.,
+                NutrientKind::Informational => false,
+            };
+            (better && round_change(change) != 0.).then_some((n, change))
+        })
+        .sorted_by(|(a, x), (b, y)| (y.abs() / b.recommended_intake).total_cmp(&(x.abs() / a.recommended_intake)))
+        .take(SUBSTITUTION_CHANGES)
+        .map(|(n, change)| (n.name.clone(), change))
+        .collect()
+}
+
+// e.g. wholemeal bread for white, the healthiest first. swaps with no
+// change big enough to show aren't suggested
+pub fn find_substitutions<'a>(
+    nutrients: &[Nutrient],
+    food: &Food,
+    foods: &'a [Food],
+    settings: &DietarySettings,
+    count: usize,
+) -> Vec<Substitution<'a>> {
+    let current = healthiness(nutrients, food, food.grams);
+    recommendable_foods(foods, settings)
+        .into_iter()
+        .filter(|f| f.name != food.name && is_similar(food, f))
+        .map(|f| (f, healthiness(nutrients, f, food.grams) - current))
+        .filter(|(_, gain)| *gain >= MIN_SUBSTITUTION_GAIN)
+        .map(|(f, gain)| (
+            Substitution {
+                food: f,
+                grams: food.grams,
+                changes: substitution_changes(nutrients, food, f, food.grams),
+            },
+            gain,
+        ))
+        .filter(|(s, _)| !s.changes.is_empty())
+        .k_largest_by(count, |(_, a), (_, b)| a.total_cmp(b))
+        .map(|(s, _)| s)
+        .collect::<Vec<Substitution>>()
+}
+
+// e.g. "swap White Bread for Wholemeal Bread: +3 g fibre, −200 mg sodium"
+pub fn explain_substitution(
+    nutrients: &[Nutrient],
+    food: &Food,
+    substitution: &Substitution,
+) -> String {
+    let changes = substitution.changes
+        .iter()
+        .map(|(name, change)| (name, round_change(*change)))
+        .filter(|(_, change)| *change != 0.)
+        .filter_map(|(name, change)| nutrients
+            .iter()
+            .find(|n| &n.name == name)
+            .map(|n| format!(
+                "{}{} {} {}",
+                if change > 0. { "+" } else { "−" },
+                format_float(change.abs()),
+                n.units,
+                mid_sentence(&n.display_name),
+            ))
+        )
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("swap {} for {}: {changes}", food.display_name, substitution.food.display_name)
+}
+
+// the food database, loaded once for the tests of every module
+#[cfg(test)]
+fn test_foods() -> (Vec<Nutrient>, Vec<Food>) {
+    static FOODS: std::sync::OnceLock<(Vec<Nutrient>, Vec<Food>)> = std::sync::OnceLock::new();
+    FOODS
+        .get_or_init(|| get_foods(
+            std::fs::read_to_string("./assets/cofid.csv").expect("cofid.csv is error free")
+        ))
+        .clone()
+}
+
 #[cfg(test)]
 mod tests {
-    fn get_foods() -> (Vec<super::Nutrient>, Vec<super::Food>) {
-        let csv = std::fs::read_to_string(
-            "./assets/cofid.csv"
-        ).expect("cofid.csv is error free");
-        super::get_foods(csv)
-    }
+    use itertools::Itertools;
+    use super::test_foods;
 
     #[test]
     fn format_floats() -> () {
@@ -235,14 +907,14 @@ mod tests {
 
     #[test]
     fn csv_parses_ok() -> () {
-        let (_nutrients, foods) = get_foods();
+        let (_nutrients, foods) = test_foods();
         assert_eq!(foods.len(), 2887);
         assert_eq!(foods[0].nutrients.len(), 58);
     }
 
     #[test]
     fn search_single_food() -> () {
-        let (_nutrients, foods) = get_foods();
+        let (_nutrients, foods) = test_foods();
 
         assert_eq!(
             super::lookup_food(
@@ -275,11 +947,17 @@ mod tests {
             )[0].display_name,
             "Baked Cooking Apples with Sugar",
         );
+
+        // weak matches are still found, only no match at all finds nothing
+        let weak = super::lookup_food(&foods, "yak milk".to_string());
+        let shown = super::search::rank(foods.iter(), |f| *f, "yak milk", &super::search::Usage::default());
+        assert!(weak.len() > shown.len());
+        assert!(super::lookup_food(&foods, "Zzyzx".to_string()).is_empty());
     }
 
     #[test]
     fn sum_nutrients() -> () {
-        let (nutrients, foods) = get_foods();
+        let (nutrients, foods) = test_foods();
         let found_foods = vec!["Ackee", "Amla", "Apples"]
             .iter()
             .map(|&s|
@@ -295,7 +973,7 @@ mod tests {
 
     #[test]
     fn recommend() -> () {
-        let (nutrients, foods) = get_foods();
+        let (nutrients, foods) = test_foods();
         let found_foods = Vec::<super::Food>::new();
         let nutrients_sum = super::sum_nutrients(
             nutrients.clone(),
@@ -306,16 +984,220 @@ mod tests {
             let recommended_foods = super::recommend_foods(
                 nutrients.clone(),
                 &foods,
-                nutrients_sum.clone()
+                nutrients_sum.clone(),
+                &super::DietarySettings::default(),
+                &super::RecommendSettings::default(),
             );
             res += recommended_foods[0].nutrients["vitamin_c_mg"];
         }
         println!("{res}");
     }
 
+    #[test]
+    fn diverse_recommendations() -> () {
+        let (nutrients, foods) = test_foods();
+        let nutrients_sum = super::sum_nutrients(
+            nutrients.clone(),
+            vec![]
+        );
+        let recommend = |diversity: f32| super::recommend_foods(
+            nutrients.clone(),
+            &foods,
+            nutrients_sum.clone(),
+            &super::DietarySettings::default(),
+            &super::RecommendSettings { count: 6, diversity, ..super::RecommendSettings::default() },
+        );
+        let plain = recommend(0.);
+        let diverse = recommend(1.);
+        assert_eq!(plain.len(), 6);
+        assert_eq!(diverse.len(), 6);
+        assert_eq!(plain[0].name, diverse[0].name);
+        let base_names = diverse
+            .iter()
+            .map(|f| super::base_name(f))
+            .collect::<std::collections::HashSet<String>>();
+        assert_eq!(base_names.len(), 6);
+
+        let most_similar = |chosen: &Vec<&super::Food>| chosen
+            .iter()
+            .tuple_combinations()
+            .map(|(a, b)| super::similarity(&nutrients, a, b))
+            .fold(0., f32::max);
+        assert!(most_similar(&diverse) < most_similar(&plain));
+    }
+
+    #[test]
+    fn explanations() -> () {
+        let (nutrients, foods) = test_foods();
+        let nutrients_sum = super::sum_nutrients(
+            nutrients.clone(),
+            vec![]
+        );
+        let recommendation = super::recommend_portions(
+            nutrients.clone(),
+            &foods,
+            nutrients_sum.clone(),
+            &super::DietarySettings::default(),
+            &super::RecommendSettings::default(),
+        ).remove(0);
+        // both are for the suggested portion
+        let portion = super::portion_food(recommendation.food, recommendation.grams);
+        assert_eq!(
+            recommendation.contributions.iter().map(|(_, s)| s).sum::<i64>(),
+            super::food_score(&nutrients, &portion, &nutrients_sum, &super::scoring::Balanced),
+        );
+        let fills = super::deficits_filled(&nutrients, &portion, &nutrients_sum, portion.grams);
+        for ((a, x), (b, y)) in recommendation.fills.iter().zip(fills.iter()) {
+            assert!(a == b && (x - y).abs() < 1e-4);
+        }
+        assert!(recommendation.fills.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert!(super::explain_recommendation(&nutrients, &recommendation).starts_with("adds "));
+
+        let made_up = super::Recommendation {
+            food: recommendation.food,
+            grams: 100.,
+            contributions: vec![],
+            fills: vec![
+                ("iron_mg".to_string(), 0.45),
+                ("folate_ug".to_string(), 0.3),
+                ("fibre_g".to_string(), 0.01),
+            ],
+        };
+        assert_eq!(
+            super::explain_recommendation(&nutrients, &made_up),
+            "adds 45% of your missing iron and 30% of folate",
+        );
+        assert_eq!(super::mid_sentence("Vitamin C"), "vitamin C");
+        assert_eq!(super::mid_sentence("AOAC fibre"), "AOAC fibre");
+    }
+
+    #[test]
+    fn foods_rich_in() -> () {
+        let (_nutrients, foods) = test_foods();
+        let settings = super::DietarySettings {
+            diets: vec![super::diet::Diet::Vegan],
+            ..super::DietarySettings::default()
+        };
+        for basis in super::BASES {
+            let rich = super::rich_in(&foods, "iron_mg", basis, &settings, 10);
+            assert_eq!(rich.len(), 10);
+            assert!(rich.iter().all(|f| f.recommend && super::is_allowed(f, &settings)));
+            assert!(rich
+                .windows(2)
+                .all(|w| super::nutrient_amount(w[0], "iron_mg", basis) >= super::nutrient_amount(w[1], "iron_mg", basis))
+            );
+        }
+        let per_gram = super::rich_in(&foods, "vitamin_c_mg", super::Basis::Per100g, &settings, 5);
+        let per_kcal = super::rich_in(&foods, "vitamin_c_mg", super::Basis::Per100kcal, &settings, 5);
+        let names = |foods: Vec<&super::Food>| foods.iter().map(|f| f.name.clone()).collect::<Vec<String>>();
+        assert_ne!(names(per_gram), names(per_kcal));
+
+        let oil = super::lookup_food(&foods, "Olive Oil".to_string()).remove(0);
+        assert_eq!(super::typical_portion(&oil), 10.);
+        assert!((super::nutrient_amount(&oil, "energy_kcal", super::Basis::Per100kcal) - 100.).abs() < 0.01);
+    }
+
+    #[test]
+    fn nutrient_kinds() -> () {
+        let (nutrients, _foods) = test_foods();
+        let find = |name: &str| nutrients
+            .iter()
+            .find(|n| n.name == name)
+            .expect("nutrient exists")
+            .clone();
+        assert_eq!(find("sodium_mg").kind, super::NutrientKind::Limit);
+        assert_eq!(find("energy_kcal").kind, super::NutrientKind::Limit);
+        assert_eq!(find("iron_mg").kind, super::NutrientKind::Target);
+        assert_eq!(find("water_g").kind, super::NutrientKind::Informational);
+        assert_eq!(find("retinol_ug").upper_limit, Some(1500.));
+        assert_eq!(find("protein_g").upper_limit, None);
+
+        assert!(super::is_over_limit(&find("sodium_mg"), 2500.));
+        assert!(!super::is_over_limit(&find("sodium_mg"), 2000.));
+        assert!(super::is_over_limit(&find("retinol_ug"), 2000.));
+        assert!(!super::is_over_limit(&find("protein_g"), 2000.));
+    }
+
+    #[test]
+    fn limits_penalise_score() -> () {
+        let (nutrients, foods) = test_foods();
+        let cheddar = super::lookup_food(
+            &foods,
+            "English Cheddar".to_string()
+        ).remove(0);
+        let mut nutrients_sum = super::sum_nutrients(
+            nutrients.clone(),
+            vec![]
+        );
+        let fresh_score = super::food_score(&nutrients, &cheddar, &nutrients_sum, &super::scoring::Balanced);
+        nutrients_sum.insert("sodium_mg".to_string(), 2400.);
+        let salty_score = super::food_score(&nutrients, &cheddar, &nutrients_sum, &super::scoring::Balanced);
+        assert!(salty_score < fresh_score - 500);
+
+        // going over on energy is only penalised once, in kcal
+        nutrients_sum.insert("energy_kcal".to_string(), 3000.);
+        nutrients_sum.insert("energy_kJ".to_string(), 12550.);
+        let contributions = super::score_contributions(&nutrients, &cheddar, &nutrients_sum, &super::scoring::Balanced);
+        assert!(contributions.iter().any(|(n, _)| n == "energy_kcal"));
+        assert!(contributions.iter().all(|(n, _)| n != "energy_kJ"));
+    }
+
+    #[test]
+    fn portions() -> () {
+        let (nutrients, foods) = test_foods();
+        let cheddar = super::lookup_food(
+            &foods,
+            "English Cheddar".to_string()
+        ).remove(0);
+        let portion = super::portion_food(&cheddar, 150.);
+        assert_eq!(portion.grams, 150.);
+        assert_eq!(portion.nutrients["sodium_mg"], cheddar.nutrients["sodium_mg"] * 1.5);
+
+        let mut nutrients_sum = super::sum_nutrients(
+            nutrients.clone(),
+            vec![]
+        );
+        let recommendations = super::recommend_portions(
+            nutrients.clone(),
+            &foods,
+            nutrients_sum.clone(),
+            &super::DietarySettings::default(),
+            &super::RecommendSettings::default(),
+        );
+        assert_eq!(recommendations.len(), 3);
+        for r in recommendations.iter() {
+            assert!(r.grams >= 5. && r.grams <= 300.);
+            assert_eq!(r.grams % 5., 0.);
+        }
+
+        let fresh_portion = super::suggest_portion(&nutrients, &cheddar, &nutrients_sum);
+        assert!(fresh_portion > 25.);
+        nutrients_sum.insert("sodium_mg".to_string(), 2300.);
+        let salty_portion = super::suggest_portion(&nutrients, &cheddar, &nutrients_sum);
+        assert!(salty_portion < fresh_portion);
+        assert!(salty_portion * cheddar.nutrients["sodium_mg"] / 100. <= 100.);
+
+        // energy is only kept to once, in kcal
+        let mut kj_over = nutrients_sum.clone();
+        kj_over.insert("energy_kJ".to_string(), 1e6);
+        assert_eq!(super::suggest_portion(&nutrients, &cheddar, &kj_over), salty_portion);
+
+        // with the limit reached there is no room for any at all
+        nutrients_sum.insert("sodium_mg".to_string(), 2400.);
+        assert_eq!(super::suggest_portion(&nutrients, &cheddar, &nutrients_sum), 0.);
+        let recommendations = super::recommend_portions(
+            nutrients.clone(),
+            &foods,
+            nutrients_sum.clone(),
+            &super::DietarySettings::default(),
+            &super::RecommendSettings::default(),
+        );
+        assert!(recommendations.iter().all(|r| r.grams >= 5. && r.food.nutrients["sodium_mg"] == 0.));
+    }
+
     #[test]
     fn highest_and_lowest_nutrients() -> () {
-        let (nutrients, foods) = get_foods();
+        let (nutrients, foods) = test_foods();
         let ackee = &foods[0];
         assert_eq!(ackee.name, "Ackee, canned, drained");
         let (highest_nutrient, lowest_nutrient) = 
@@ -341,4 +1223,100 @@ mod tests {
         assert_eq!(highest_nutrient.name, "folate_ug");
         assert_eq!(lowest_nutrient.name, "fibre_g");
     }
+
+    #[test]
+    fn excesses() -> () {
+        let (nutrients, foods) = test_foods();
+        let cheddar = super::lookup_food(&foods, "English Cheddar".to_string()).remove(0);
+        let apple = super::lookup_food(&foods, "Apple".to_string()).remove(0);
+        let eaten = vec![apple.clone(), super::portion_food(&cheddar, 300.)];
+        assert_eq!(super::find_excesses(&nutrients, &[apple]).len(), 0);
+
+        let excesses = super::find_excesses(&nutrients, &eaten);
+        assert!(!excesses.is_empty());
+        assert!(excesses
+            .windows(2)
+            .all(|w| w[0].total / w[0].limit >= w[1].total / w[1].limit)
+        );
+        let saturated_fat = excesses
+            .iter()
+            .find(|e| e.nutrient.name == "saturated_fat_g")
+            .expect("300 g of cheddar is a lot of saturated fat");
+        let (i, amount) = saturated_fat.contributors[0];
+        assert_eq!(i, 1);
+
+        // eating the reduced portion instead brings it back under
+        let grams = super::reduced_portion(saturated_fat, &eaten[i], amount);
+        assert!(grams > 0. && grams < 300.);
+        let reduced = vec![eaten[0].clone(), super::portion_food(&cheddar, grams)];
+        let nutrients_sum = super::sum_nutrients(nutrients.clone(), reduced);
+        assert!(nutrients_sum["saturated_fat_g"] <= saturated_fat.limit);
+        let explanation = super::explain_excess(saturated_fat, &eaten);
+        assert!(explanation.starts_with("You have had"));
+        assert!(explanation.contains("saturated fatty acids limit, 100% of it from English Cheddar"), "{explanation}");
+        assert!(explanation.contains(&format!("Try {grams} g of it instead of 300 g.")));
+
+        // salt is the highest nutrient of crisps, but isn't cheered on
+        let crisps = super::lookup_food(&foods, "Crisps".to_string()).remove(0);
+        let (highest_nutrient, _) = super::get_highest_and_lowest_nutrients(
+            nutrients.clone(), crisps.nutrients.clone(),
+        );
+        assert_ne!(highest_nutrient.kind, super::NutrientKind::Limit);
+    }
+
+    #[test]
+    fn substitutions() -> () {
+        let (nutrients, foods) = test_foods();
+        let settings = super::DietarySettings::default();
+        let white = foods
+            .iter()
+            .find(|f| f.name == "Bread, white, average")
+            .expect("there is white bread");
+        let toast = super::portion_food(white, 80.);
+        let substitutions = super::find_substitutions(&nutrients, &toast, &foods, &settings, 5);
+        assert!(!substitutions.is_empty());
+        assert!(substitutions.iter().any(|s| s.food.name.to_lowercase().contains("wholemeal")));
+        for s in substitutions.iter() {
+            assert_eq!(s.food.group, white.group);
+            assert_eq!(s.grams, 80.);
+            assert!(!s.changes.is_empty() && s.changes.len() <= 3);
+        }
+
+        let wholemeal = substitutions
+            .iter()
+            .find(|s| s.food.name.to_lowercase().contains("wholemeal"))
+            .expect("checked above");
+        // every change is for the better
+        for (name, change) in wholemeal.changes.iter() {
+            let nutrient = nutrients.iter().find(|n| &n.name == name).expect("changes are nutrients");
+            match nutrient.kind {
+                super::NutrientKind::Limit => assert!(*change < 0.),
+                _ => assert!(*change > 0.),
+            }
+        }
+        let explanation = super::explain_substitution(&nutrients, &toast, wholemeal);
+        assert!(explanation.starts_with(&format!("swap {} for {}: +", white.display_name, wholemeal.food.display_name)));
+        assert_eq!(explanation.matches(", ").count(), wholemeal.changes.len() - 1, "{explanation}");
+        // changes too small to show are left out of the explanation
+        let tiny = super::Substitution {
+            changes: vec![("fibre_g".to_string(), 3.), ("sodium_mg".to_string(), -0.04)],
+            ..wholemeal.clone()
+        };
+        let explanation = super::explain_substitution(&nutrients, &toast, &tiny);
+        assert!(explanation.contains(": +3 g ") && !explanation.contains(", "), "{explanation}");
+
+        // and so are swaps that leave nothing to show, however little is eaten
+        let crumb = super::portion_food(white, 5.);
+        let swaps = super::find_substitutions(&nutrients, &crumb, &foods, &settings, 100);
+        assert!(!swaps.is_empty());
+        for s in swaps.iter() {
+            let explanation = super::explain_substitution(&nutrients, &crumb, s);
+            assert!(!explanation.ends_with(": "), "{explanation}");
+            assert!(!explanation.contains("+0 ") && !explanation.contains("−0 "), "{explanation}");
+        }
+
+        // nothing healthier than the healthiest
+        let best = super::find_substitutions(&nutrients, wholemeal.food, &foods, &settings, 100);
+        assert!(best.iter().all(|s| s.food.name != toast.name));
+    }
 }
@@ -0,0 +1,406 @@
+use super::{Food, Nutrient, RecommendSettings, DietarySettings, get_foods, lookup_food, portion_food, sum_nutrients, recommend_portions, explain_recommendation, format_float};
+use super::scoring::{SCORERS, parse_scorer, scorer_id, scorer_description};
+use super::optimise::{Constraints, Objective, optimise_diet, remaining_energy, targets_met};
+use super::plan::{PlanSettings, generate_plan, plan_text};
+use super::shopping::{ListFormat, shopping_list, export_list};
+use super::search::{Usage, build_index, search_foods, rank};
+use super::filters::{parse_search_filters, search_query, filtered_amounts};
+use super::query::{parse_food_query, run_query, explain_error};
+use super::recommendable_foods;
+use std::time::Instant;
+
+
+const USAGE: &str = "\
+usage: nutrition [--data PATH] COMMAND
+
+commands:
+  recommend [--scorer ID] [--count N] [--no-variety] [FOOD ...]
+      suggest foods to eat after the given foods, each a search term
+      optionally starting with an amount like \"150g cheddar\"
+  plan [--kcal N] [--most-targets] [FOOD ...]
+      work out exact amounts to eat after the given foods within the
+      energy left, or N kcal, either getting close to every target or
+      reaching as many as possible
+  week [--kcal N] [--shopping] [--markdown]
+      plan meals for the next seven days at the energy RI or N kcal a
+      day, or list what to buy for them as plain text or markdown
+  search QUERY ...
+      find foods by name and by nutrients per 100 g, with filters like
+      protein>5, sugar<=5 or high:iron
+  query QUERY
+      list foods matching a condition on nutrients per 100 g, like
+      \"protein_g / energy_kcal * 100 > 10 and sodium_mg < 200
+      order by fibre_g desc limit 20\"
+  search-bench [SEARCH ...]
+      time each keystroke of typing the searches, matching against every
+      food and with the search index
+  scorers
+      list the ids accepted by --scorer
+  help
+      show this message";
+
+const DEFAULT_DATA: &str = "./assets/cofid.csv";
+
+// takes the value following a flag
+fn flag_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<&'a String, String> {
+    args.next().ok_or(format!("{flag} needs a value"))
+}
+
+fn load(data: &str) -> Result<(Vec<Nutrient>, Vec<Food>), String> {
+    std::fs::read_to_string(data)
+        .map(get_foods)
+        .map_err(|e| format!("couldn't read {data}: {e}"))
+}
+
+// "150g cheddar" is 150 grams of the best match for cheddar
+fn find_eaten(foods: &Vec<Food>, term: &str) -> Result<Food, String> {
+    let (grams, search) = match term.split_once(' ') {
+        Some((amount, rest)) if amount.ends_with('g') && amount[..amount.len() - 1].parse::<f32>().is_ok() =>
+            (amount[..amount.len() - 1].parse::<f32>().expect("checked above"), rest),
+        _ => (100., term),
+    };
+    lookup_food(foods, search.to_string())
+        .first()
+        .map(|f| portion_food(f, grams))
+        .ok_or(format!("no food matches {search}"))
+}
+
+fn recommend<'a>(
+    data: &str,
+    mut args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let mut settings = RecommendSettings::default();
+    let mut terms = Vec::<&String>::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scorer" => {
+                let id = flag_value(&mut args, arg)?;
+                settings.scorer = parse_scorer(id).ok_or(format!(
+                    "unknown scorer {id}, try one of {}",
+                    SCORERS.map(scorer_id).join(", "),
+                ))?;
+            },
+            "--count" => {
+                let count = flag_value(&mut args, arg)?;
+                settings.count = count
+                    .parse()
+                    .map_err(|_| format!("--count expects a number, not {count}"))?;
+            },
+            "--no-variety" => settings.diversity = 0.,
+            _ => terms.push(arg),
+        }
+    }
+
+    let (nutrients, foods) = load(data)?;
+    let eaten = terms
+        .iter()
+        .map(|t| find_eaten(&foods, t))
+        .collect::<Result<Vec<Food>, String>>()?;
+    let nutrients_sum = sum_nutrients(nutrients.clone(), eaten.clone());
+    let recommendations = recommend_portions(
+        nutrients.clone(),
+        &foods,
+        nutrients_sum,
+        &DietarySettings::default(),
+        &settings,
+    );
+
+    let mut lines = eaten
+        .iter()
+        .map(|f| format!("ate {}g of {} {}", format_float(f.grams), f.emoji, f.display_name))
+        .collect::<Vec<String>>();
+    lines.push(format!("recommended by {}:", scorer_id(settings.scorer)));
+    lines.extend(recommendations.iter().map(|r| format!(
+        "  {}g of {} {}, {}",
+        format_float(r.grams),
+        r.food.emoji,
+        r.food.display_name,
+        explain_recommendation(&nutrients, r),
+    )));
+    Ok(lines.join("\n"))
+}
+
+fn plan<'a>(
+    data: &str,
+    mut args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let mut energy_budget = None::<f32>;
+    let mut objective = Objective::Shortfall;
+    let mut terms = Vec::<&String>::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kcal" => {
+                let kcal = flag_value(&mut args, arg)?;
+                energy_budget = Some(kcal
+                    .parse()
+                    .map_err(|_| format!("--kcal expects a number, not {kcal}"))?);
+            },
+            "--most-targets" => objective = Objective::TargetsMet,
+            _ => terms.push(arg),
+        }
+    }
+
+    let (nutrients, foods) = load(data)?;
+    let eaten = terms
+        .iter()
+        .map(|t| find_eaten(&foods, t))
+        .collect::<Result<Vec<Food>, String>>()?;
+    let nutrients_sum = sum_nutrients(nutrients.clone(), eaten.clone());
+    let constraints = Constraints {
+        energy_budget_kcal: energy_budget.unwrap_or(remaining_energy(&nutrients, &nutrients_sum)),
+        objective,
+        ..Constraints::default()
+    };
+    let plan = optimise_diet(
+        &nutrients,
+        &nutrients_sum,
+        &recommendable_foods(&foods, &DietarySettings::default()),
+        &constraints,
+    ).map_err(|e| format!("couldn't work out a plan, {e}"))?;
+
+    let mut lines = vec![format!("within {:.0} kcal:", constraints.energy_budget_kcal)];
+    lines.extend(plan.portions.iter().map(|p| format!(
+        "  {}g of {} {}",
+        format_float(p.grams),
+        p.food.emoji,
+        p.food.display_name,
+    )));
+    lines.push(format!(
+        "reaching {} targets, up from {}",
+        targets_met(&nutrients, &plan.totals),
+        targets_met(&nutrients, &nutrients_sum),
+    ));
+    Ok(lines.join("\n"))
+}
+
+fn week<'a>(
+    data: &str,
+    mut args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let mut plan_settings = PlanSettings::default();
+    let (mut shopping, mut format) = (false, ListFormat::Text);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shopping" => shopping = true,
+            "--markdown" => format = ListFormat::Markdown,
+            "--kcal" => {
+                let kcal = flag_value(&mut args, arg)?;
+                plan_settings.daily_energy_kcal = Some(kcal
+                    .parse()
+                    .map_err(|_| format!("--kcal expects a number, not {kcal}"))?);
+            },
+            other => return Err(format!("unknown option {other}")),
+        }
+    }
+    let (nutrients, foods) = load(data)?;
+    let plan = generate_plan(&nutrients, &foods, &DietarySettings::default(), &plan_settings, None);
+    match shopping {
+        true => Ok(export_list(&shopping_list(&foods, &plan), format)),
+        false => Ok(plan_text(&foods, &plan)),
+    }
+}
+
+fn search<'a>(
+    data: &str,
+    args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let search = args.map(|a| a.as_str()).collect::<Vec<&str>>().join(" ");
+    let (nutrients, foods) = load(data)?;
+    let query = parse_search_filters(&nutrients, &search)?;
+    if query.text.is_empty() && query.filters.is_empty() {
+        return Err("search needs something to search for".to_string());
+    }
+    let matches = search_query(&build_index(&foods), &foods, &query, |_| true, &Usage::default());
+    if matches.is_empty() {
+        return Err(format!("no food matches {search}"));
+    }
+    Ok(matches
+        .iter()
+        .map(|m| {
+            let food = &foods[m.id];
+            match query.filters.len() {
+                0 => format!("{} {}", food.emoji, food.display_name),
+                _ => format!("{} {} ({})", food.emoji, food.display_name, filtered_amounts(&nutrients, &query, food)),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+fn query<'a>(
+    data: &str,
+    args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let text = args.map(|a| a.as_str()).collect::<Vec<&str>>().join(" ");
+    if text.trim().is_empty() {
+        return Err("query needs a condition or an order, like fibre_g > 10".to_string());
+    }
+    let (nutrients, foods) = load(data)?;
+    let query = parse_food_query(&nutrients, &text).map_err(|e| explain_error(&text, &e))?;
+    let results = run_query(&query, &foods);
+    if results.is_empty() {
+        return Err(format!("no food matches {text}"));
+    }
+    Ok(results
+        .iter()
+        .map(|(i, value)| {
+            let food = &foods[*i];
+            match value {
+                Some(value) => format!("{} {} ({})", food.emoji, food.display_name, format_float(*value)),
+                None => format!("{} {}", food.emoji, food.display_name),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+const BENCH_SEARCHES: [&str; 4] = ["semi skimmed milk", "english cheddar", "baked beans", "chdr"];
+const BENCH_REPEATS: usize = 10;
+
+// typing "milk" searches for "m", "mi", "mil" and then "milk"
+fn keystrokes(search: &str) -> Vec<&str> {
+    search
+        .char_indices()
+        .map(|(i, c)| &search[..i + c.len_utf8()])
+        .collect()
+}
+
+// average microseconds a keystroke takes
+fn time_keystrokes<T>(search: &str, mut run_search: impl FnMut(&str) -> T) -> f64 {
+    let start = Instant::now();
+    for _ in 0..BENCH_REPEATS {
+        for k in keystrokes(search) {
+            std::hint::black_box(run_search(k));
+        }
+    }
+    start.elapsed().as_secs_f64() * 1e6 / (BENCH_REPEATS * keystrokes(search).len()) as f64
+}
+
+fn search_bench<'a>(
+    data: &str,
+    args: impl Iterator<Item = &'a String>,
+) -> Result<String, String> {
+    let mut searches = args.map(|a| a.as_str()).collect::<Vec<&str>>();
+    if searches.is_empty() {
+        searches = BENCH_SEARCHES.to_vec();
+    }
+    let (_, foods) = load(data)?;
+    let start = Instant::now();
+    let index = build_index(&foods);
+    let mut lines = vec![format!(
+        "built the index of {} foods in {:.1} ms",
+        foods.len(),
+        start.elapsed().as_secs_f64() * 1e3,
+    )];
+    lines.extend(searches.iter().map(|search| format!(
+        "{search}: {:.0} µs a keystroke matching every food, {:.0} µs with the index",
+        time_keystrokes(search, |k| rank(0..foods.len(), |i| &foods[*i], k, &Usage::default())),
+        time_keystrokes(search, |k| search_foods(&index, &foods, k, |_| true, &Usage::default())),
+    )));
+    Ok(lines.join("\n"))
+}
+
+pub fn run(args: &[String]) -> Result<String, String> {
+    let mut args = args.iter();
+    let mut data = DEFAULT_DATA;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data" => data = flag_value(&mut args, arg)?,
+            "recommend" => return recommend(data, args),
+            "plan" => return plan(data, args),
+            "week" => return week(data, args),
+            "search" => return search(data, args),
+            "query" => return query(data, args),
+            "search-bench" => return search_bench(data, args),
+            "scorers" => return Ok(SCORERS
+                .iter()
+                .map(|k| format!("{:<10}{}", scorer_id(*k), scorer_description(*k)))
+                .collect::<Vec<String>>()
+                .join("\n")
+            ),
+            "help" | "--help" | "-h" => return Ok(USAGE.to_string()),
+            other => return Err(format!("unknown command {other}\n\n{USAGE}")),
+        }
+    }
+    Err(USAGE.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    fn run(args: &[&str]) -> Result<String, String> {
+        super::run(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn scorers() -> () {
+        assert!(run(&["scorers"]).expect("scorers lists").contains("deficit"));
+        assert!(run(&[]).is_err());
+        assert!(run(&["frobnicate"]).is_err());
+    }
+
+    #[test]
+    fn recommend() -> () {
+        let output = run(&["recommend", "--scorer", "energy", "--count", "2", "150g English Cheddar"])
+            .expect("recommend works");
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "ate 150g of 🧀 English Cheddar");
+        assert_eq!(lines[1], "recommended by energy:");
+
+        let error = run(&["recommend", "--scorer", "tasty"]).expect_err("tasty isn't a scorer");
+        assert!(error.contains("balanced, deficit, energy, density"));
+        assert!(run(&["--data", "./nowhere.csv", "recommend"]).is_err());
+    }
+
+    #[test]
+    fn plan() -> () {
+        let output = run(&["plan", "--kcal", "500", "--most-targets", "Cornflakes"])
+            .expect("plan works");
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "within 500 kcal:");
+        assert!(lines.len() > 2);
+        assert!(lines[lines.len() - 1].starts_with("reaching "));
+        assert!(run(&["plan", "--kcal", "lots"]).is_err());
+    }
+
+    #[test]
+    fn week() -> () {
+        let output = run(&["week", "--kcal", "1800"]).expect("week works");
+        assert!(output.starts_with("Day 1\n"));
+        assert!(output.contains("Day 7\n"));
+        assert!(run(&["week", "--days", "3"]).is_err());
+        let output = run(&["week", "--shopping", "--markdown"]).expect("shopping works");
+        assert!(output.starts_with("## "));
+        assert!(output.contains("\n- [ ] "));
+    }
+
+    #[test]
+    fn search() -> () {
+        let output = run(&["search", "yogurt", "protein>5", "sugar<=5"]).expect("search works");
+        assert!(output.starts_with("🥛 Plain Greek Yogurt (protein 5.7 g, sugars 4.5 g)"), "{output}");
+        assert_eq!(run(&["search", "high:iron"]).expect("search works").lines().count(), 20);
+        assert!(run(&["search", "yogurt", "gluten<1"]).is_err());
+        assert!(run(&["search"]).is_err());
+    }
+
+    #[test]
+    fn query() -> () {
+        let output = run(&["query", "fibre_g > 20 and sugar_g < 5 order by fibre_g desc limit 3"])
+            .expect("query works");
+        assert_eq!(output.lines().count(), 3);
+        let error = run(&["query", "fibre_g >> 20"]).expect_err("it doesn't parse");
+        assert_eq!(error, "fibre_g >> 20\n         ^ expected a number or a nutrient, found >");
+        assert!(run(&["query"]).is_err());
+    }
+
+    #[test]
+    fn search_bench() -> () {
+        let output = run(&["search-bench", "milk"]).expect("search-bench works");
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert!(lines[0].starts_with("built the index of "));
+        assert!(lines[1].starts_with("milk: ") && lines[1].ends_with(" µs with the index"));
+    }
+}
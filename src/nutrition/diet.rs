@@ -0,0 +1,155 @@
+use serde::{Serialize, Deserialize};
+use super::Food;
+
+
+// things a food contains which people might avoid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Tag {
+    Meat,
+    Fish,
+    Shellfish,
+    Dairy,
+    Egg,
+    Honey,
+    Gluten,
+    Nuts,
+    Soy,
+}
+
+pub const ALLERGENS: [Tag; 7] = [
+    Tag::Gluten,
+    Tag::Nuts,
+    Tag::Dairy,
+    Tag::Egg,
+    Tag::Soy,
+    Tag::Fish,
+    Tag::Shellfish,
+];
+
+pub fn parse_tag(s: &str) -> Option<Tag> {
+    match s {
+        "meat" => Some(Tag::Meat),
+        "fish" => Some(Tag::Fish),
+        "shellfish" => Some(Tag::Shellfish),
+        "dairy" => Some(Tag::Dairy),
+        "egg" => Some(Tag::Egg),
+        "honey" => Some(Tag::Honey),
+        "gluten" => Some(Tag::Gluten),
+        "nuts" => Some(Tag::Nuts),
+        "soy" => Some(Tag::Soy),
+        _ => None,
+    }
+}
+
+pub fn tag_name(tag: Tag) -> &'static str {
+    match tag {
+        Tag::Meat => "🥩 Meat",
+        Tag::Fish => "🐟 Fish",
+        Tag::Shellfish => "🦐 Shellfish",
+        Tag::Dairy => "🥛 Dairy",
+        Tag::Egg => "🥚 Egg",
+        Tag::Honey => "🍯 Honey",
+        Tag::Gluten => "🌾 Gluten",
+        Tag::Nuts => "🥜 Nuts",
+        Tag::Soy => "🫛 Soy",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Diet {
+    Vegetarian,
+    Vegan,
+    Pescatarian,
+    GlutenFree,
+}
+
+pub const DIETS: [Diet; 4] = [
+    Diet::Vegetarian,
+    Diet::Vegan,
+    Diet::Pescatarian,
+    Diet::GlutenFree,
+];
+
+pub fn diet_name(diet: Diet) -> &'static str {
+    match diet {
+        Diet::Vegetarian => "🥕 Vegetarian",
+        Diet::Vegan => "🌱 Vegan",
+        Diet::Pescatarian => "🐟 Pescatarian",
+        Diet::GlutenFree => "🌾 Gluten-free",
+    }
+}
+
+fn diet_excludes(diet: Diet) -> Vec<Tag> {
+    match diet {
+        Diet::Vegetarian => vec![Tag::Meat, Tag::Fish, Tag::Shellfish],
+        Diet::Vegan => vec![
+            Tag::Meat, Tag::Fish, Tag::Shellfish, Tag::Dairy, Tag::Egg, Tag::Honey,
+        ],
+        Diet::Pescatarian => vec![Tag::Meat],
+        Diet::GlutenFree => vec![Tag::Gluten],
+    }
+}
+
+pub fn fits_diet(food: &Food, diet: Diet) -> bool {
+    !diet_excludes(diet)
+        .iter()
+        .any(|t| food.tags.contains(t))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DietarySettings {
+    pub diets: Vec<Diet>,
+    pub allergens: Vec<Tag>,
+    pub filter_search: bool,
+}
+
+pub fn is_allowed(food: &Food, settings: &DietarySettings) -> bool {
+    settings.diets.iter().all(|d| fits_diet(food, *d))
+        && !settings.allergens.iter().any(|t| food.tags.contains(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tag, Diet};
+    use super::super::test_foods;
+
+    #[test]
+    fn dietary_filters() -> () {
+        let (nutrients, foods) = test_foods();
+        let salmon = foods
+            .iter()
+            .find(|f| f.name.starts_with("Salmon"))
+            .expect("there is salmon");
+        assert!(super::fits_diet(salmon, Diet::Pescatarian));
+        assert!(!super::fits_diet(salmon, Diet::Vegetarian));
+
+        let settings = super::DietarySettings {
+            diets: vec![Diet::Vegetarian],
+            allergens: vec![Tag::Nuts],
+            filter_search: false,
+        };
+        let allowed = foods
+            .iter()
+            .filter(|f| super::is_allowed(f, &settings))
+            .collect::<Vec<&super::Food>>();
+        assert!(allowed.len() > 1000);
+        assert!(allowed.iter().all(|f| !f.tags.contains(&Tag::Meat) && !f.tags.contains(&Tag::Nuts)));
+
+        let nutrients_sum = super::super::sum_nutrients(nutrients.clone(), vec![]);
+        let recommended_foods = super::super::recommend_foods(
+            nutrients,
+            &foods,
+            nutrients_sum,
+            &settings,
+            &super::super::RecommendSettings::default(),
+        );
+        assert!(recommended_foods.iter().all(|f| super::is_allowed(f, &settings)));
+
+        let found_foods = super::super::lookup_allowed_food(
+            &foods,
+            "salmon".to_string(),
+            &settings,
+        );
+        assert!(found_foods.iter().all(|f| !f.tags.contains(&Tag::Fish)));
+    }
+}
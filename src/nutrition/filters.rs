@@ -0,0 +1,276 @@
+use itertools::Itertools;
+use super::{Food, Nutrient, Basis, nutrient_amount, format_float};
+use super::search::{SearchIndex, SearchMatch, Usage, SEARCH_RESULTS, search_foods};
+
+
+// "high:" and "low:" follow the labelling rules of thumb, a food is high in
+// a nutrient with 30% of the RI per 100 g and low in it with 5%
+const HIGH_FRACTION: f32 = 0.3;
+const LOW_FRACTION: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+// longest first so that ">=" isn't read as ">"
+const COMPARISONS: [(&str, Comparison); 4] = [
+    (">=", Comparison::AtLeast),
+    ("<=", Comparison::AtMost),
+    (">", Comparison::Above),
+    ("<", Comparison::Below),
+];
+
+pub fn comparison_symbol(comparison: Comparison) -> &'static str {
+    match comparison {
+        Comparison::Above => ">",
+        Comparison::AtLeast => "≥",
+        Comparison::Below => "<",
+        Comparison::AtMost => "≤",
+    }
+}
+
+// amounts are per 100 g
+#[derive(Debug, Clone, PartialEq)]
+pub struct NutrientFilter {
+    pub nutrient: String,
+    pub comparison: Comparison,
+    pub amount: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchFilters {
+    // what's left of the search to match against names
+    pub text: String,
+    pub filters: Vec<NutrientFilter>,
+}
+
+// "protein", "protein_g", "Protein" and "prot" are all protein_g
+pub fn find_nutrient<'a>(nutrients: &'a [Nutrient], name: &str) -> Option<&'a Nutrient> {
+    let name = name.to_lowercase();
+    nutrients.iter().find(|n| {
+        n.name.to_lowercase() == name
+            || n.name.rsplit_once('_').is_some_and(|(stem, _)| stem.to_lowercase() == name)
+            || n.display_name.to_lowercase() == name
+            || n.abbreviation.to_lowercase() == name
+    })
+}
+
+fn known_nutrient<'a>(nutrients: &'a [Nutrient], name: &str) -> Result<&'a Nutrient, String> {
+    find_nutrient(nutrients, name).ok_or(format!("unknown nutrient {name}"))
+}
+
+// "high:iron" or "low:sugar"
+fn parse_claim(nutrients: &[Nutrient], word: &str) -> Result<Option<NutrientFilter>, String> {
+    let (claim, name) = match word.split_once(':') {
+        Some((claim, name)) => (claim.to_lowercase(), name),
+        None => return Ok(None),
+    };
+    let (comparison, fraction) = match claim.as_str() {
+        "high" => (Comparison::AtLeast, HIGH_FRACTION),
+        "low" => (Comparison::AtMost, LOW_FRACTION),
+        _ => return Ok(None),
+    };
+    let nutrient = known_nutrient(nutrients, name)?;
+    if nutrient.recommended_intake <= 0. {
+        return Err(format!("{claim}:{name} needs a nutrient with a reference intake"));
+    }
+    Ok(Some(NutrientFilter {
+        nutrient: nutrient.name.clone(),
+        comparison,
+        amount: nutrient.recommended_intake * fraction,
+    }))
+}
+
+// "protein>8" or "sugar<=5g"
+fn parse_threshold(nutrients: &[Nutrient], word: &str) -> Result<Option<NutrientFilter>, String> {
+    let (symbol, comparison) = match COMPARISONS.iter().find(|(s, _)| word.contains(s)) {
+        Some(c) => *c,
+        None => return Ok(None),
+    };
+    let (name, amount) = word.split_once(symbol).expect("checked above");
+    let nutrient = known_nutrient(nutrients, name)?;
+    let number = amount.strip_suffix(nutrient.units.as_str()).unwrap_or(amount);
+    let amount = number
+        .parse::<f32>()
+        .map_err(|_| format!("{name}{symbol} needs an amount in {}, not {amount:?}", nutrient.units))?;
+    Ok(Some(NutrientFilter {
+        nutrient: nutrient.name.clone(),
+        comparison,
+        amount,
+    }))
+}
+
+fn parse_filter(nutrients: &[Nutrient], word: &str) -> Result<Option<NutrientFilter>, String> {
+    match parse_claim(nutrients, word)? {
+        Some(filter) => Ok(Some(filter)),
+        None => parse_threshold(nutrients, word),
+    }
+}
+
+// "yogurt protein>8 sugar<5" is a search for yogurt with two filters
+pub fn parse_search_filters(nutrients: &[Nutrient], search: &str) -> Result<SearchFilters, String> {
+    let mut text = vec![];
+    let mut filters = vec![];
+    for word in search.split_whitespace() {
+        match parse_filter(nutrients, word)? {
+            Some(filter) => filters.push(filter),
+            None => text.push(word),
+        }
+    }
+    Ok(SearchFilters { text: text.join(" "), filters })
+}
+
+// the same for a search still being typed, where the last word may be a
+// filter that isn't finished yet like "protein>" or "high:ir". that word
+// is left out until it parses or is followed by a space
+pub fn parse_typed_filters(nutrients: &[Nutrient], search: &str) -> Result<SearchFilters, String> {
+    let (typed, typing) = match search.ends_with(char::is_whitespace) {
+        true => (search, ""),
+        false => search.rsplit_once(char::is_whitespace).unwrap_or(("", search)),
+    };
+    let mut query = parse_search_filters(nutrients, typed)?;
+    match parse_filter(nutrients, typing) {
+        Ok(Some(filter)) => query.filters.push(filter),
+        Ok(None) if !typing.is_empty() => {
+            query.text = [query.text.as_str(), typing].iter().filter(|t| !t.is_empty()).join(" ");
+        },
+        _ => (),
+    }
+    Ok(query)
+}
+
+pub fn passes(filter: &NutrientFilter, food: &Food) -> bool {
+    let amount = nutrient_amount(food, &filter.nutrient, Basis::Per100g);
+    match filter.comparison {
+        Comparison::Above => amount > filter.amount,
+        Comparison::AtLeast => amount >= filter.amount,
+        Comparison::Below => amount < filter.amount,
+        Comparison::AtMost => amount <= filter.amount,
+    }
+}
+
+// e.g. "protein ≥ 18 g"
+pub fn describe_filter(nutrients: &[Nutrient], filter: &NutrientFilter) -> String {
+    let nutrient = find_nutrient(nutrients, &filter.nutrient);
+    format!(
+        "{} {} {} {}",
+        nutrient.map_or(filter.nutrient.clone(), |n| n.display_name.to_lowercase()),
+        comparison_symbol(filter.comparison),
+        format_float(filter.amount),
+        nutrient.map_or("", |n| n.units.as_str()),
+    )
+}
+
+// the filtered nutrients in 100 g of the food, e.g. "protein 9.2 g, sugars 3 g"
+pub fn filtered_amounts(nutrients: &[Nutrient], query: &SearchFilters, food: &Food) -> String {
+    query.filters
+        .iter()
+        .map(|f| &f.nutrient)
+        .unique()
+        .filter_map(|name| find_nutrient(nutrients, name))
+        .map(|n| format!(
+            "{} {} {}",
+            n.display_name.to_lowercase(),
+            format_float(nutrient_amount(food, &n.name, Basis::Per100g)),
+            n.units,
+        ))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// foods passing every filter, by name when there is text to match and
+// otherwise by how far they go in the direction of the first filter
+pub fn search_query(
+    index: &SearchIndex,
+    foods: &[Food],
+    query: &SearchFilters,
+    allowed: impl Fn(&Food) -> bool,
+    usage: &Usage,
+) -> Vec<SearchMatch> {
+    let wanted = |f: &Food| allowed(f) && query.filters.iter().all(|q| passes(q, f));
+    if !query.text.is_empty() {
+        return search_foods(index, foods, &query.text, wanted, usage);
+    }
+    let first = match query.filters.first() {
+        Some(first) => first,
+        None => return vec![],
+    };
+    let amount = |i: &usize| nutrient_amount(&foods[*i], &first.nutrient, Basis::Per100g);
+    (0..foods.len())
+        .filter(|i| wanted(&foods[*i]))
+        .sorted_by(|a, b| match first.comparison {
+            Comparison::Above | Comparison::AtLeast => amount(b).total_cmp(&amount(a)),
+            Comparison::Below | Comparison::AtMost => amount(a).total_cmp(&amount(b)),
+        })
+        .take(SEARCH_RESULTS)
+        .map(|i| SearchMatch { id: i, score: 0, indices: vec![] })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Basis, nutrient_amount};
+    use super::super::search::{Usage, build_index};
+    use super::Comparison;
+    use super::super::test_foods;
+
+    #[test]
+    fn nutrient_filters() -> () {
+        let (nutrients, foods) = test_foods();
+
+        let query = super::parse_search_filters(&nutrients, "yogurt protein>5 Sugar<=5g").expect("it parses");
+        assert_eq!(query.text, "yogurt");
+        assert_eq!(query.filters, vec![
+            super::NutrientFilter { nutrient: "protein_g".to_string(), comparison: Comparison::Above, amount: 5. },
+            super::NutrientFilter { nutrient: "sugar_g".to_string(), comparison: Comparison::AtMost, amount: 5. },
+        ]);
+        assert_eq!(super::describe_filter(&nutrients, &query.filters[1]), "sugars ≤ 5 g");
+
+        let high = super::parse_search_filters(&nutrients, "high:iron").expect("it parses");
+        assert_eq!(high.text, "");
+        assert_eq!(high.filters[0].comparison, Comparison::AtLeast);
+        assert_eq!(high.filters[0].amount, 15. * 0.3);
+
+        assert_eq!(super::parse_search_filters(&nutrients, "gluten>1"), Err("unknown nutrient gluten".to_string()));
+        assert!(super::parse_search_filters(&nutrients, "protein>lots").is_err());
+        assert!(super::parse_search_filters(&nutrients, "high:water").is_err());
+        // a colon on its own is just text
+        assert_eq!(super::parse_search_filters(&nutrients, "note:").expect("it parses").text, "note:");
+
+        // while typing, an unfinished filter at the end is left out rather
+        // than blanking the results, but finished words still have to parse
+        for typing in ["yogurt protein>", "yogurt protein>5 high:", "yogurt protein>5 high:ir", "yogurt Sugar<=5m"] {
+            let typed = super::parse_typed_filters(&nutrients, typing).expect("it parses so far");
+            assert_eq!(typed.text, "yogurt", "{typing}");
+        }
+        assert_eq!(super::parse_typed_filters(&nutrients, "yogurt protein>5 Sugar<=5g"), Ok(query.clone()));
+        assert_eq!(super::parse_typed_filters(&nutrients, "protein>5 yog").expect("it parses").text, "yog");
+        assert!(super::parse_typed_filters(&nutrients, "protein> yogurt").is_err());
+        assert!(super::parse_typed_filters(&nutrients, "yogurt high:ir ").is_err());
+
+        let index = build_index(&foods);
+        let yogurts = super::search_query(&index, &foods, &query, |_| true, &Usage::default());
+        assert!(!yogurts.is_empty());
+        for m in yogurts.iter() {
+            let food = &foods[m.id];
+            assert!(food.nutrients["protein_g"] > 5. && food.nutrients["sugar_g"] <= 5.);
+        }
+        let yogurt = &foods[yogurts[0].id];
+        assert_eq!(yogurt.name, "Yogurt, Greek style, plain");
+        assert!(super::filtered_amounts(&nutrients, &query, yogurt).starts_with("protein "));
+
+        // with nothing to match by name the richest come first
+        let iron = super::search_query(&index, &foods, &high, |_| true, &Usage::default());
+        assert_eq!(iron.len(), super::SEARCH_RESULTS);
+        let amounts = iron
+            .iter()
+            .map(|m| nutrient_amount(&foods[m.id], "iron_mg", Basis::Per100g))
+            .collect::<Vec<f32>>();
+        assert!(amounts.windows(2).all(|a| a[0] >= a[1]));
+        assert!(amounts.iter().all(|a| *a >= 4.5));
+    }
+}
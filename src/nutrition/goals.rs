@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use super::{Nutrient, NutrientKind};
+use super::plan::{Meal, MEALS};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Sex {
+    Female,
+    Male,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ActivityLevel {
+    Sedentary,
+    Light,
+    Moderate,
+    Active,
+    VeryActive,
+}
+
+pub const ACTIVITY_LEVELS: [ActivityLevel; 5] = [
+    ActivityLevel::Sedentary,
+    ActivityLevel::Light,
+    ActivityLevel::Moderate,
+    ActivityLevel::Active,
+    ActivityLevel::VeryActive,
+];
+
+pub fn activity_description(activity: ActivityLevel) -> &'static str {
+    match activity {
+        ActivityLevel::Sedentary => "Sedentary (little or no exercise)",
+        ActivityLevel::Light => "Light (exercise 1-3 days a week)",
+        ActivityLevel::Moderate => "Moderate (exercise 3-5 days a week)",
+        ActivityLevel::Active => "Active (exercise 6-7 days a week)",
+        ActivityLevel::VeryActive => "Very active (physical job or training twice a day)",
+    }
+}
+
+fn activity_factor(activity: ActivityLevel) -> f32 {
+    match activity {
+        ActivityLevel::Sedentary => 1.2,
+        ActivityLevel::Light => 1.375,
+        ActivityLevel::Moderate => 1.55,
+        ActivityLevel::Active => 1.725,
+        ActivityLevel::VeryActive => 1.9,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub weight_kg: f32,
+    pub height_cm: f32,
+    pub age_years: f32,
+    pub sex: Sex,
+    pub activity: ActivityLevel,
+    // scale protein, fat, carbohydrate and fibre along with energy
+    pub scale_macros: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            weight_kg: 70.,
+            height_cm: 170.,
+            age_years: 30.,
+            sex: Sex::Female,
+            activity: ActivityLevel::Light,
+            scale_macros: true,
+        }
+    }
+}
+
+// Mifflin-St Jeor equation, in kcal per day
+pub fn basal_metabolic_rate(profile: &Profile) -> f32 {
+    let offset = match profile.sex {
+        Sex::Female => -161.,
+        Sex::Male => 5.,
+    };
+    10. * profile.weight_kg
+        + 6.25 * profile.height_cm
+        - 5. * profile.age_years
+        + offset
+}
+
+pub fn total_energy_expenditure(profile: &Profile) -> f32 {
+    basal_metabolic_rate(profile) * activity_factor(profile.activity)
+}
+
+const MACRO_NUTRIENTS: [&str; 4] = [
+    "protein_g",
+    "fat_g",
+    "carbohydrate_g",
+    "fibre_g",
+];
+
+pub fn apply_energy_goal(
+    nutrients: Vec<Nutrient>,
+    energy_kcal: f32,
+    scale_macros: bool,
+) -> Vec<Nutrient> {
+    // the dataset's RIs are all relative to its own energy_kcal row
+    let reference_kcal = nutrients
+        .iter()
+        .find(|n| n.name == "energy_kcal")
+        .map_or(energy_kcal, |n| n.recommended_intake);
+    // a nonsense profile would zero or flip the sign of every RI
+    if energy_kcal <= 0. || reference_kcal <= 0. {
+        return nutrients;
+    }
+    let ratio = energy_kcal / reference_kcal;
+    nutrients
+        .into_iter()
+        .map(|mut n| {
+            let scale = match n.name.as_str() {
+                "energy_kcal" | "energy_kJ" => true,
+                name => scale_macros && MACRO_NUTRIENTS.contains(&name),
+            };
+            if scale {
+                n.recommended_intake *= ratio;
+            }
+            n
+        })
+        .collect()
+}
+
+pub fn apply_profile(
+    nutrients: Vec<Nutrient>,
+    profile: &Option<Profile>,
+) -> Vec<Nutrient> {
+    match profile {
+        Some(profile) => apply_energy_goal(
+            nutrients,
+            total_energy_expenditure(profile),
+            profile.scale_macros,
+        ),
+        None => nutrients,
+    }
+}
+
+// a user's own goal for a nutrient, e.g. set by their doctor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NutrientGoal {
+    pub kind: Option<NutrientKind>,
+    pub target: Option<f32>,
+    pub limit: Option<f32>,
+}
+
+pub fn apply_custom_goals(
+    nutrients: Vec<Nutrient>,
+    goals: &HashMap<String, NutrientGoal>,
+) -> Vec<Nutrient> {
+    nutrients
+        .into_iter()
+        .map(|mut n| {
+            let Some(goal) = goals.get(&n.name) else {
+                return n;
+            };
+            if let Some(kind) = goal.kind {
+                n.kind = kind;
+            }
+            if let Some(target) = goal.target {
+                if goal.kind.is_none() {
+                    n.kind = NutrientKind::Target;
+                }
+                n.recommended_intake = target;
+            }
+            if let Some(limit) = goal.limit {
+                // a limit on something we're aiming for is an upper limit,
+                // otherwise the nutrient becomes something to stay under
+                if n.kind == NutrientKind::Target {
+                    n.upper_limit = Some(limit);
+                } else {
+                    n.kind = NutrientKind::Limit;
+                    n.recommended_intake = limit;
+                }
+            }
+            n
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Preset {
+    LowSodium,
+    Renal,
+    // saved as LowCarb while it only had a daily limit
+    #[serde(alias = "LowCarb")]
+    CarbCounting,
+    HighProtein,
+}
+
+pub const PRESETS: [Preset; 4] = [
+    Preset::LowSodium,
+    Preset::Renal,
+    Preset::CarbCounting,
+    Preset::HighProtein,
+];
+
+// grams of carbohydrate each meal can have, so that blood sugar stays
+// steady through the day rather than just the day's total being right
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CarbBudget {
+    pub meal_g: f32,
+    pub snacks_g: f32,
+}
+
+const CARB_COUNTING: CarbBudget = CarbBudget { meal_g: 60., snacks_g: 20. };
+
+pub fn preset_carb_budget(preset: Preset) -> Option<CarbBudget> {
+    match preset {
+        Preset::CarbCounting => Some(CARB_COUNTING),
+        _ => None,
+    }
+}
+
+pub fn meal_carbohydrate_g(budget: &CarbBudget, meal: Meal) -> f32 {
+    match meal {
+        Meal::Snacks => budget.snacks_g,
+        _ => budget.meal_g,
+    }
+}
+
+pub fn preset_name(preset: Preset) -> &'static str {
+    match preset {
+        Preset::LowSodium => "🧂 Low sodium",
+        Preset::Renal => "🫘 Kidney friendly",
+        Preset::CarbCounting => "🍞 Carb counting",
+        Preset::HighProtein => "🏋️ High protein",
+    }
+}
+
+pub fn preset_description(preset: Preset) -> &'static str {
+    match preset {
+        Preset::LowSodium =>
+            "For high blood pressure: at most 1500mg of sodium, plenty of potassium and less saturated fat.",
+        Preset::Renal =>
+            "For kidney disease: limits potassium, phosphorus, sodium and protein.",
+        Preset::CarbCounting =>
+            "For diabetes: at most 60g of carbohydrate at each meal and 20g in snacks, less sugar and plenty of fibre.",
+        Preset::HighProtein =>
+            "For training: 120g of protein a day.",
+    }
+}
+
+fn target(value: f32) -> NutrientGoal {
+    NutrientGoal { kind: Some(NutrientKind::Target), target: Some(value), limit: None }
+}
+
+fn limit(value: f32) -> NutrientGoal {
+    NutrientGoal { kind: Some(NutrientKind::Limit), target: None, limit: Some(value) }
+}
+
+pub fn preset_goals(preset: Preset) -> HashMap<String, NutrientGoal> {
+    let goals = match preset {
+        Preset::LowSodium => vec![
+            ("sodium_mg", limit(1500.)),
+            ("potassium_mg", target(4700.)),
+            ("saturated_fat_g", limit(15.)),
+        ],
+        Preset::Renal => vec![
+            ("potassium_mg", limit(2000.)),
+            ("phosphorus_mg", limit(800.)),
+            ("sodium_mg", limit(2000.)),
+            ("protein_g", NutrientGoal { limit: Some(70.), ..target(50.) }),
+        ],
+        Preset::CarbCounting => vec![
+            // every meal's budget added up
+            ("carbohydrate_g", limit(MEALS.map(|m| meal_carbohydrate_g(&CARB_COUNTING, m)).iter().sum())),
+            ("sugar_g", limit(50.)),
+        ],
+        Preset::HighProtein => vec![
+            ("protein_g", target(120.)),
+        ],
+    };
+    goals
+        .into_iter()
+        .map(|(name, goal)| (name.to_string(), goal))
+        .collect()
+}
+
+// the nutrients shown up front in the report
+pub fn preset_highlights(preset: Preset) -> Vec<&'static str> {
+    match preset {
+        Preset::LowSodium => vec!["sodium_mg", "potassium_mg", "saturated_fat_g", "fibre_g"],
+        Preset::Renal => vec!["potassium_mg", "phosphorus_mg", "sodium_mg", "protein_g"],
+        Preset::CarbCounting => vec!["carbohydrate_g", "sugar_g", "fibre_g", "energy_kcal"],
+        Preset::HighProtein => vec!["protein_g", "energy_kcal", "carbohydrate_g", "fat_g"],
+    }
+}
+
+pub fn apply_preset(
+    nutrients: Vec<Nutrient>,
+    preset: &Option<Preset>,
+) -> Vec<Nutrient> {
+    match preset {
+        Some(preset) => apply_custom_goals(nutrients, &preset_goals(*preset)),
+        None => nutrients,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_foods;
+
+    fn find_nutrient(nutrients: &[super::Nutrient], name: &str) -> super::Nutrient {
+        nutrients
+            .iter()
+            .find(|n| n.name == name)
+            .expect("nutrient exists")
+            .clone()
+    }
+
+    fn find(nutrients: &[super::Nutrient], name: &str) -> f32 {
+        find_nutrient(nutrients, name).recommended_intake
+    }
+
+    #[test]
+    fn energy_expenditure() -> () {
+        let profile = super::Profile {
+            weight_kg: 70.,
+            height_cm: 175.,
+            age_years: 30.,
+            sex: super::Sex::Male,
+            activity: super::ActivityLevel::Moderate,
+            scale_macros: true,
+        };
+        assert_eq!(super::basal_metabolic_rate(&profile), 1648.75);
+        assert_eq!(super::total_energy_expenditure(&profile).round(), 2556.);
+
+        let profile = super::Profile { sex: super::Sex::Female, ..profile };
+        assert_eq!(super::basal_metabolic_rate(&profile), 1482.75);
+    }
+
+    #[test]
+    fn energy_goal_scales_macros() -> () {
+        let nutrients = test_foods().0;
+
+        let scaled = super::apply_energy_goal(nutrients.clone(), 1125., true);
+        assert_eq!(find(&scaled, "energy_kcal"), 1125.);
+        assert_eq!(find(&scaled, "energy_kJ"), 4700.);
+        assert_eq!(find(&scaled, "protein_g"), 30.);
+        assert_eq!(find(&scaled, "fibre_g"), 15.);
+        assert_eq!(find(&scaled, "iron_mg"), find(&nutrients, "iron_mg"));
+
+        let unscaled = super::apply_energy_goal(nutrients.clone(), 1125., false);
+        assert_eq!(find(&unscaled, "energy_kcal"), 1125.);
+        assert_eq!(find(&unscaled, "protein_g"), 60.);
+
+        let untouched = super::apply_profile(nutrients.clone(), &None);
+        assert_eq!(find(&untouched, "energy_kcal"), 2250.);
+
+        // a profile giving no energy leaves the RIs as they are
+        let empty = super::Profile { weight_kg: 0., height_cm: 0., ..super::Profile::default() };
+        assert!(super::total_energy_expenditure(&empty) <= 0.);
+        let ignored = super::apply_profile(nutrients.clone(), &Some(empty));
+        assert_eq!(find(&ignored, "energy_kcal"), 2250.);
+        assert_eq!(find(&ignored, "protein_g"), 60.);
+    }
+
+    #[test]
+    fn custom_goals_override() -> () {
+        let nutrients = test_foods().0;
+        let goals = std::collections::HashMap::from([
+            ("sodium_mg".to_string(), super::NutrientGoal { limit: Some(2000.), ..Default::default() }),
+            ("protein_g".to_string(), super::NutrientGoal { target: Some(120.), ..Default::default() }),
+            ("iron_mg".to_string(), super::NutrientGoal { limit: Some(20.), ..Default::default() }),
+            ("cholesterol_mg".to_string(), super::NutrientGoal { limit: Some(300.), ..Default::default() }),
+        ]);
+        let custom = super::apply_custom_goals(nutrients.clone(), &goals);
+
+        let sodium = find_nutrient(&custom, "sodium_mg");
+        assert_eq!(sodium.kind, super::NutrientKind::Limit);
+        assert_eq!(sodium.recommended_intake, 2000.);
+
+        let protein = find_nutrient(&custom, "protein_g");
+        assert_eq!(protein.kind, super::NutrientKind::Target);
+        assert_eq!(protein.recommended_intake, 120.);
+
+        let iron = find_nutrient(&custom, "iron_mg");
+        assert_eq!(iron.recommended_intake, 15.);
+        assert_eq!(iron.upper_limit, Some(20.));
+
+        let cholesterol = find_nutrient(&custom, "cholesterol_mg");
+        assert_eq!(cholesterol.kind, super::NutrientKind::Limit);
+        assert_eq!(cholesterol.recommended_intake, 300.);
+
+        assert_eq!(find(&custom, "vitamin_c_mg"), 40.);
+    }
+
+    #[test]
+    fn presets() -> () {
+        let nutrients = test_foods().0;
+
+        let renal = super::apply_preset(nutrients.clone(), &Some(super::Preset::Renal));
+        let potassium = find_nutrient(&renal, "potassium_mg");
+        assert_eq!(potassium.kind, super::NutrientKind::Limit);
+        assert_eq!(potassium.recommended_intake, 2000.);
+        let protein = find_nutrient(&renal, "protein_g");
+        assert_eq!(protein.kind, super::NutrientKind::Target);
+        assert_eq!(protein.recommended_intake, 50.);
+        assert_eq!(protein.upper_limit, Some(70.));
+
+        let carbs = super::apply_preset(nutrients.clone(), &Some(super::Preset::CarbCounting));
+        assert_eq!(find_nutrient(&carbs, "carbohydrate_g").kind, super::NutrientKind::Limit);
+        assert_eq!(find(&carbs, "carbohydrate_g"), 200.);
+        let budget = super::preset_carb_budget(super::Preset::CarbCounting).expect("carbs are counted");
+        assert_eq!(super::meal_carbohydrate_g(&budget, super::Meal::Dinner), 60.);
+        assert_eq!(super::meal_carbohydrate_g(&budget, super::Meal::Snacks), 20.);
+        assert_eq!(super::preset_carb_budget(super::Preset::Renal), None);
+
+        for preset in super::PRESETS {
+            for name in super::preset_highlights(preset) {
+                find_nutrient(&nutrients, name);
+            }
+            for name in super::preset_goals(preset).keys() {
+                find_nutrient(&nutrients, name);
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use itertools::Itertools;
+use serde::{Serialize, Deserialize};
+use super::Nutrient;
+
+
+// nutrients the body stores, so that going without for a day or two
+// doesn't matter as long as the week adds up
+pub const STORED_NUTRIENTS: [&str; 5] = [
+    "retinol_ug",
+    "vitamin_d_ug",
+    "vitamin_e_mg",
+    "vitamin_k1_ug",
+    "vitamin_b12_ug",
+];
+
+// old days are dropped so local storage doesn't grow forever
+const MAX_HISTORY_DAYS: i64 = 90;
+
+// a day counts half as much for how often a food is eaten after this many days
+const USAGE_HALF_LIFE_DAYS: f32 = 14.;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DayTotals {
+    // days since 1970-01-01
+    pub day: i64,
+    pub totals: HashMap<String, f32>,
+    // cofid names of the foods logged, missing from days recorded before
+    // foods were
+    #[serde(default)]
+    pub foods: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AveragingSettings {
+    // length of the rolling window including today, 1 is off
+    pub days: usize,
+    // which nutrients are judged over the window rather than day by day
+    pub averaged: Vec<String>,
+}
+
+impl Default for AveragingSettings {
+    fn default() -> Self {
+        AveragingSettings {
+            days: 1,
+            averaged: STORED_NUTRIENTS.map(|n| n.to_string()).to_vec(),
+        }
+    }
+}
+
+// keeps one entry per day, in order
+pub fn record_day(
+    history: &mut Vec<DayTotals>,
+    day: i64,
+    totals: HashMap<String, f32>,
+    foods: Vec<String>,
+) -> () {
+    history.retain(|d| d.day != day);
+    history.push(DayTotals { day, totals, foods });
+    let latest = history.iter().map(|d| d.day).max().unwrap_or(day);
+    history.retain(|d| d.day > latest - MAX_HISTORY_DAYS);
+    history.sort_by_key(|d| d.day);
+}
+
+// for when everything logged on a day has been taken off again
+pub fn forget_day(history: &mut Vec<DayTotals>, day: i64) -> () {
+    history.retain(|d| d.day != day);
+}
+
+// logged days before today inside the window, days with nothing logged
+// aren't counted as days of eating nothing
+fn window<'a>(
+    history: &'a [DayTotals],
+    today: i64,
+    settings: &AveragingSettings,
+) -> Vec<&'a DayTotals> {
+    let first = today - settings.days.max(1) as i64 + 1;
+    history
+        .iter()
+        .filter(|d| d.day >= first && d.day < today)
+        .collect()
+}
+
+fn is_averaged(nutrient: &Nutrient, settings: &AveragingSettings) -> bool {
+    settings.days > 1 && settings.averaged.contains(&nutrient.name)
+}
+
+// what to treat as eaten today so that filling the RI from here brings the
+// average over the window up to it, the same as carrying over whatever the
+// earlier days were ahead or behind by
+pub fn effective_sum(
+    nutrients: &[Nutrient],
+    nutrients_sum: &HashMap<String, f32>,
+    history: &[DayTotals],
+    today: i64,
+    settings: &AveragingSettings,
+) -> HashMap<String, f32> {
+    let earlier = window(history, today, settings);
+    nutrients
+        .iter()
+        .map(|n| {
+            let sum = nutrients_sum[&n.name];
+            let sum = match is_averaged(n, settings) {
+                true => earlier
+                    .iter()
+                    .fold(sum, |a, d| a + d.totals.get(&n.name).unwrap_or(&0.) - n.recommended_intake),
+                false => sum,
+            };
+            (n.name.clone(), sum)
+        })
+        .collect::<HashMap<String, f32>>()
+}
+
+// average daily intake over the window including today, for the nutrients
+// which are averaged
+pub fn rolling_average(
+    nutrients: &[Nutrient],
+    nutrients_sum: &HashMap<String, f32>,
+    history: &[DayTotals],
+    today: i64,
+    settings: &AveragingSettings,
+) -> HashMap<String, f32> {
+    let earlier = window(history, today, settings);
+    nutrients
+        .iter()
+        .filter(|n| is_averaged(n, settings))
+        .map(|n| (
+            n.name.clone(),
+            earlier
+                .iter()
+                .fold(nutrients_sum[&n.name], |a, d| a + d.totals.get(&n.name).unwrap_or(&0.))
+                / (earlier.len() + 1) as f32,
+        ))
+        .collect::<HashMap<String, f32>>()
+}
+
+// how much of the time each food is eaten, from 0 to 1 for every logged
+// day, with recent days counting for more
+pub fn food_usage(history: &[DayTotals], today: i64) -> HashMap<String, f32> {
+    let recency = |d: &DayTotals| 0.5_f32.powf((today - d.day).max(0) as f32 / USAGE_HALF_LIFE_DAYS);
+    let logged = history
+        .iter()
+        .filter(|d| !d.foods.is_empty())
+        .map(recency)
+        .sum::<f32>();
+    let mut usage = HashMap::<String, f32>::new();
+    for d in history.iter() {
+        for food in d.foods.iter().unique() {
+            *usage.entry(food.clone()).or_insert(0.) += recency(d) / logged;
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::super::{Nutrient, NutrientKind};
+
+    fn nutrient(name: &str, recommended_intake: f32) -> Nutrient {
+        Nutrient {
+            name: name.to_string(),
+            display_name: name.to_string(),
+            abbreviation: name.to_string(),
+            units: "".to_string(),
+            recommended_intake,
+            kind: NutrientKind::Target,
+            upper_limit: None,
+        }
+    }
+
+    fn totals(retinol: f32, vitamin_c: f32) -> HashMap<String, f32> {
+        HashMap::from([
+            ("retinol_ug".to_string(), retinol),
+            ("vitamin_c_mg".to_string(), vitamin_c),
+        ])
+    }
+
+    #[test]
+    fn averaging() -> () {
+        let nutrients = vec![nutrient("retinol_ug", 700.), nutrient("vitamin_c_mg", 40.)];
+        let mut history = vec![];
+        super::record_day(&mut history, 100, totals(2100., 80.), vec![]);
+        super::record_day(&mut history, 98, totals(0., 10.), vec![]);
+        super::record_day(&mut history, 100, totals(2100., 80.), vec![]);
+        super::record_day(&mut history, 1, totals(9999., 9999.), vec![]);
+        assert_eq!(history.iter().map(|d| d.day).collect::<Vec<i64>>(), vec![98, 100]);
+        super::record_day(&mut history, 101, totals(5., 5.), vec![]);
+        super::forget_day(&mut history, 101);
+        assert_eq!(history.iter().map(|d| d.day).collect::<Vec<i64>>(), vec![98, 100]);
+
+        // day 99 wasn't logged so only two earlier days count
+        let today = totals(0., 0.);
+        let weekly = super::AveragingSettings { days: 7, ..super::AveragingSettings::default() };
+        let sum = super::effective_sum(&nutrients, &today, &history, 101, &weekly);
+        assert_eq!(sum["retinol_ug"], 700.);
+        assert_eq!(sum["vitamin_c_mg"], 0.);
+        let average = super::rolling_average(&nutrients, &today, &history, 101, &weekly);
+        assert_eq!(average["retinol_ug"], 700.);
+        assert!(!average.contains_key("vitamin_c_mg"));
+
+        // a three day window only reaches back to day 99
+        let short = super::AveragingSettings { days: 3, ..weekly.clone() };
+        assert_eq!(super::effective_sum(&nutrients, &today, &history, 101, &short)["retinol_ug"], 1400.);
+
+        // off by default
+        let daily = super::AveragingSettings::default();
+        assert_eq!(super::effective_sum(&nutrients, &today, &history, 101, &daily), today);
+    }
+
+    #[test]
+    fn usage() -> () {
+        let mut history = vec![];
+        let foods = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+        super::record_day(&mut history, 100, totals(0., 0.), foods(&["milk", "tea", "milk"]));
+        super::record_day(&mut history, 86, totals(0., 0.), foods(&["milk", "cake"]));
+        super::record_day(&mut history, 80, totals(0., 0.), vec![]);
+
+        // days before foods were logged don't count
+        let usage = super::food_usage(&history, 100);
+        assert!((usage["milk"] - 1.).abs() < 1e-6);
+        assert!((usage["tea"] - 2. / 3.).abs() < 1e-6);
+        assert!((usage["cake"] - 1. / 3.).abs() < 1e-6);
+        assert!(!usage.contains_key("toast"));
+        assert_eq!(super::food_usage(&[], 100).len(), 0);
+    }
+}
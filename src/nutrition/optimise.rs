@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use microlp::{ComparisonOp, OptimizationDirection, Problem};
+use super::{Food, Nutrient, NutrientKind, energy_kcal, is_duplicate, typical_portion};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    // get as close as possible to every target, counting what is missing
+    // of each as a fraction of its RI
+    Shortfall,
+    // reach as many targets as possible, e.g. when planning dinner
+    TargetsMet,
+}
+
+pub fn objective_name(objective: Objective) -> &'static str {
+    match objective {
+        Objective::Shortfall => "🧮 Get close to everything",
+        Objective::TargetsMet => "🍽️ Reach the most targets",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraints {
+    // most of any one food
+    pub max_grams: f32,
+    // amounts are whole multiples of this so they are easy to weigh out
+    pub step_grams: f32,
+    // energy left to spend today
+    pub energy_budget_kcal: f32,
+    // least energy the foods should add up to, so that a planned meal is filling
+    pub min_energy_kcal: f32,
+    // most different foods to suggest
+    pub max_foods: usize,
+    // keep limit nutrients and upper limits from going any further over
+    pub respect_limits: bool,
+    // keep each food to a couple of typical portions of it
+    pub typical_portions: bool,
+    pub objective: Objective,
+    // most time any one solve may take, so the page stays responsive if
+    // branch and bound gets stuck. None solves to the end, which always
+    // gives the same answer
+    pub time_limit: Option<Duration>,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Constraints {
+            max_grams: 250.,
+            step_grams: 25.,
+            energy_budget_kcal: 800.,
+            min_energy_kcal: 0.,
+            max_foods: 4,
+            respect_limits: true,
+            typical_portions: false,
+            objective: Objective::Shortfall,
+            time_limit: Some(Duration::from_secs(2)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Portion<'a> {
+    pub food: &'a Food,
+    pub grams: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Optimisation<'a> {
+    pub portions: Vec<Portion<'a>>,
+    // what nutrients_sum becomes once the portions are eaten
+    pub totals: HashMap<String, f32>,
+}
+
+// whatever is left of the energy RI, used as the default budget
+pub fn remaining_energy(
+    nutrients: &[Nutrient],
+    nutrients_sum: &HashMap<String, f32>,
+) -> f32 {
+    nutrients
+        .iter()
+        .find(|n| n.name == "energy_kcal")
+        .map_or(0., |n| (n.recommended_intake - nutrients_sum[&n.name]).max(0.))
+}
+
+// how many targets will be reached once everything in totals is eaten
+pub fn targets_met(nutrients: &[Nutrient], totals: &HashMap<String, f32>) -> usize {
+    nutrients
+        .iter()
+        .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+        .filter(|n| totals[&n.name] >= n.recommended_intake * (1. - NEARLY_MET))
+        .count()
+}
+
+// most portions of a food when keeping to typical portions
+const TYPICAL_PORTIONS: f32 = 2.;
+// tiny cost per 100 g so that of two equally good plans the lighter one wins
+const AMOUNT_COST: f64 = 0.001;
+// targets this close count as reached, so rounding to whole steps doesn't
+// miss them by a hair
+const NEARLY_MET: f32 = 0.01;
+// when counting targets reached, the shortfall only breaks ties
+const TIEBREAK_SHORTFALL_COST: f64 = 0.01;
+
+// a food's nutrient row is for food.grams, which is 100 unless it has
+// already been portioned, so amounts are worked out from it per 100 g
+fn per_100g(food: &Food, name: &str) -> f32 {
+    let value = match name {
+        "energy_kcal" => energy_kcal(food),
+        _ => food.nutrients[name],
+    };
+    value * 100. / food.grams
+}
+
+// returns grams of each food, either in whole steps or continuous, and
+// optionally with at most max_foods of the foods switched on
+fn solve(
+    nutrients: &[Nutrient],
+    nutrients_sum: &HashMap<String, f32>,
+    foods: &Vec<&Food>,
+    constraints: &Constraints,
+    in_steps: bool,
+    limit_foods: bool,
+) -> Result<Vec<f32>, String> {
+    let mut problem = Problem::new(OptimizationDirection::Minimize);
+    let max_steps = (constraints.max_grams / constraints.step_grams).floor() as i32;
+    // hundreds of grams that one unit of each amount variable stands for
+    let unit = constraints.step_grams as f64 / 100.;
+    let amounts = foods
+        .iter()
+        .map(|f| {
+            let max_steps = match constraints.typical_portions {
+                true => max_steps.min(
+                    ((TYPICAL_PORTIONS * typical_portion(f) / constraints.step_grams).floor() as i32).max(1)
+                ),
+                false => max_steps,
+            };
+            if in_steps {
+                problem.add_integer_var(AMOUNT_COST * unit, (0, max_steps))
+            } else {
+                problem.add_var(AMOUNT_COST * unit, (0., max_steps as f64))
+            }
+        })
+        .collect::<Vec<_>>();
+    if limit_foods {
+        let switches = amounts
+            .iter()
+            .map(|&a| {
+                let switch = problem.add_binary_var(0.);
+                problem.add_constraint(
+                    [(a, 1.), (switch, -max_steps as f64)],
+                    ComparisonOp::Le,
+                    0.,
+                );
+                (switch, 1.)
+            })
+            .collect::<Vec<_>>();
+        problem.add_constraint(switches, ComparisonOp::Le, constraints.max_foods as f64);
+    }
+    let content = |name: &str, scale: f32| amounts
+        .iter()
+        .zip(foods.iter())
+        .map(|(&a, f)| (a, (per_100g(f, name) / scale) as f64 * unit))
+        .collect::<Vec<_>>();
+
+    // energy is kept to through the budget, in kcal only
+    for n in nutrients.iter().filter(|n| n.recommended_intake > 0.1 && !is_duplicate(n)) {
+        let sum = nutrients_sum[&n.name];
+        match n.kind {
+            // shortfall is measured as a fraction of the RI so every
+            // nutrient counts the same however big its units are
+            NutrientKind::Target if sum < n.recommended_intake => {
+                let deficit = n.recommended_intake - sum;
+                let shortfall_cost = match constraints.objective {
+                    Objective::Shortfall => 1.,
+                    Objective::TargetsMet => TIEBREAK_SHORTFALL_COST,
+                };
+                let shortfall = problem.add_var(shortfall_cost, (0., f64::INFINITY));
+                let mut filled = content(&n.name, n.recommended_intake);
+                filled.push((shortfall, 1.));
+                problem.add_constraint(
+                    filled,
+                    ComparisonOp::Ge,
+                    (deficit / n.recommended_intake) as f64,
+                );
+                // met can only be switched on once the deficit is filled
+                if constraints.objective == Objective::TargetsMet && deficit > n.recommended_intake * NEARLY_MET {
+                    let met = if in_steps || limit_foods {
+                        problem.add_binary_var(-1.)
+                    } else {
+                        problem.add_var(-1., (0., 1.))
+                    };
+                    let mut filled = content(&n.name, deficit * (1. - NEARLY_MET));
+                    filled.push((met, -1.));
+                    problem.add_constraint(filled, ComparisonOp::Ge, 0.);
+                }
+            },
+            NutrientKind::Limit if constraints.respect_limits => {
+                problem.add_constraint(
+                    content(&n.name, 1.),
+                    ComparisonOp::Le,
+                    (n.recommended_intake - sum).max(0.) as f64,
+                );
+            },
+            _ => (),
+        }
+        if let (Some(ul), true) = (n.upper_limit, constraints.respect_limits) {
+            problem.add_constraint(
+                content(&n.name, 1.),
+                ComparisonOp::Le,
+                (ul - sum).max(0.) as f64,
+            );
+        }
+    }
+    problem.add_constraint(
+        content("energy_kcal", 1.),
+        ComparisonOp::Le,
+        constraints.energy_budget_kcal.max(0.) as f64,
+    );
+    if constraints.min_energy_kcal > 0. {
+        problem.add_constraint(
+            content("energy_kcal", 1.),
+            ComparisonOp::Ge,
+            constraints.min_energy_kcal as f64,
+        );
+    }
+
+    if let Some(time_limit) = constraints.time_limit {
+        problem.set_time_limit(time_limit);
+    }
+    let solution = problem
+        .solve()
+        .map_err(|e| match e {
+            microlp::Error::Infeasible => "no amounts keep to every constraint".to_string(),
+            e => format!("the solver failed: {e}"),
+        })?
+        .into_solution()
+        .map_err(|_| "the solver ran out of time".to_string())?;
+    Ok(
+        amounts
+            .iter()
+            .map(|&a| (solution.var_value(a) * unit * 100.) as f32)
+            .collect()
+    )
+}
+
+// the linear relaxation over the whole pool picks out the handful of foods
+// worth considering, choosing max_foods of those is a small mixed integer
+// programme, and then their amounts are settled exactly in whole steps.
+// an error means no answer was found, not that nothing would help, which
+// is an answer with no portions. foods may be portioned already, the
+// grams suggested are always of the food itself
+pub fn optimise_diet<'a>(
+    nutrients: &[Nutrient],
+    nutrients_sum: &HashMap<String, f32>,
+    foods: &Vec<&'a Food>,
+    constraints: &Constraints,
+) -> Result<Optimisation<'a>, String> {
+    let mut portions = foods
+        .iter()
+        .map(|&f| Portion { food: f, grams: 0. })
+        .collect::<Vec<Portion>>();
+    for (in_steps, limit_foods) in [(false, false), (false, true), (true, false)] {
+        if portions.is_empty() {
+            break;
+        }
+        let candidates = portions.iter().map(|p| p.food).collect::<Vec<&Food>>();
+        let grams = match solve(nutrients, nutrients_sum, &candidates, constraints, in_steps, limit_foods) {
+            Ok(grams) => grams,
+            // whole steps can miss a minimum energy, but rounding down the
+            // amounts found so far never goes over a limit
+            Err(_) if in_steps => portions
+                .iter()
+                .map(|p| (p.grams / constraints.step_grams).floor() * constraints.step_grams)
+                .collect(),
+            Err(e) => return Err(e),
+        };
+        portions = candidates
+            .into_iter()
+            .zip(grams)
+            .filter(|(_, g)| *g > 0.5)
+            .map(|(f, g)| Portion { food: f, grams: g.round() })
+            .collect();
+    }
+    portions.sort_by(|a, b| b.grams.total_cmp(&a.grams));
+    // energy counts the way the budget was kept to
+    let totals = nutrients
+        .iter()
+        .map(|n| (
+            n.name.clone(),
+            portions
+                .iter()
+                .fold(
+                    nutrients_sum[&n.name],
+                    |a, p| a + p.grams / 100. * per_100g(p.food, &n.name),
+                ),
+        ))
+        .collect::<HashMap<String, f32>>();
+    Ok(Optimisation {
+        portions,
+        totals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Nutrient, NutrientKind, sum_nutrients};
+    use super::super::test_foods;
+
+    // fraction of each target's RI still missing, added up
+    fn shortfall(nutrients: &[Nutrient], totals: &std::collections::HashMap<String, f32>) -> f32 {
+        nutrients
+            .iter()
+            .filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1)
+            .map(|n| (1. - totals[&n.name] / n.recommended_intake).max(0.))
+            .sum()
+    }
+
+    #[test]
+    fn optimise() -> () {
+        let (nutrients, foods) = test_foods();
+        let pool = super::super::recommendable_foods(
+            &foods,
+            &super::super::DietarySettings::default(),
+        );
+        let breakfast = super::super::lookup_food(
+            &foods,
+            "Cornflakes".to_string()
+        );
+        let nutrients_sum = sum_nutrients(nutrients.clone(), breakfast[..1].to_vec());
+        let constraints = super::Constraints {
+            energy_budget_kcal: super::remaining_energy(&nutrients, &nutrients_sum),
+            ..super::Constraints::default()
+        };
+        let plan = super::optimise_diet(&nutrients, &nutrients_sum, &pool, &constraints)
+            .expect("there is a plan");
+
+        assert!(!plan.portions.is_empty());
+        assert!(plan.portions.len() <= constraints.max_foods);
+        for p in plan.portions.iter() {
+            assert!(p.grams <= constraints.max_grams);
+            assert_eq!(p.grams % constraints.step_grams, 0.);
+        }
+        assert!(plan.totals["energy_kcal"] <= nutrients_sum["energy_kcal"] + constraints.energy_budget_kcal + 1.);
+        for n in nutrients.iter().filter(|n| n.kind == NutrientKind::Limit && n.recommended_intake > 0.1) {
+            assert!(plan.totals[&n.name] <= n.recommended_intake.max(nutrients_sum[&n.name]) + 0.1, "{}", n.name);
+        }
+        assert!(shortfall(&nutrients, &plan.totals) < shortfall(&nutrients, &nutrients_sum) / 2.);
+        // energy is totalled the same way the budget is kept to
+        let energy = plan.portions
+            .iter()
+            .fold(nutrients_sum["energy_kcal"], |a, p| a + super::super::energy_kcal(p.food) * p.grams / 100.);
+        assert!((plan.totals["energy_kcal"] - energy).abs() < 0.1);
+        // and only in kcal, the kJ row isn't kept to a second time
+        let mut kj_over = nutrients_sum.clone();
+        kj_over.insert("energy_kJ".to_string(), 1e6);
+        let kj_plan = super::optimise_diet(&nutrients, &kj_over, &pool, &constraints)
+            .expect("there is a plan");
+        let amounts = |o: &super::Optimisation| o.portions
+            .iter()
+            .map(|p| (p.food.name.clone(), p.grams))
+            .collect::<Vec<(String, f32)>>();
+        assert_eq!(amounts(&kj_plan), amounts(&plan));
+        // foods that have already been portioned are planned per 100 g
+        let portioned = pool
+            .iter()
+            .map(|&f| super::super::portion_food(f, 40.))
+            .collect::<Vec<super::super::Food>>();
+        let portioned_plan = super::optimise_diet(&nutrients, &nutrients_sum, &portioned.iter().collect(), &constraints)
+            .expect("there is a plan");
+        assert_eq!(amounts(&portioned_plan), amounts(&plan));
+        for (n, total) in portioned_plan.totals.iter() {
+            assert!((total - plan.totals[n]).abs() <= 1e-3 * plan.totals[n].abs().max(1.), "{n}");
+        }
+
+        // no energy to spend leaves only foods without any, and spices with
+        // unknown energy don't count as such
+        let starved = super::optimise_diet(
+            &nutrients,
+            &nutrients_sum,
+            &pool,
+            &super::Constraints { energy_budget_kcal: 0., ..constraints.clone() },
+        ).expect("there is a plan");
+        assert!(starved.portions.iter().all(|p| super::super::energy_kcal(p.food) < 1.));
+
+        // planning dinner within a smaller budget reaches more targets than
+        // getting close to all of them does
+        let dinner = super::Constraints { energy_budget_kcal: 500., ..constraints.clone() };
+        let closest = super::optimise_diet(&nutrients, &nutrients_sum, &pool, &dinner)
+            .expect("there is a plan");
+        let most_met = super::optimise_diet(
+            &nutrients,
+            &nutrients_sum,
+            &pool,
+            &super::Constraints { objective: super::Objective::TargetsMet, ..dinner.clone() },
+        ).expect("there is a plan");
+        let met = super::targets_met(&nutrients, &most_met.totals);
+        assert!(met > super::targets_met(&nutrients, &closest.totals));
+        assert!(met > super::targets_met(&nutrients, &nutrients_sum));
+        assert!(most_met.portions.len() <= dinner.max_foods);
+        assert!(most_met.totals["energy_kcal"] <= nutrients_sum["energy_kcal"] + dinner.energy_budget_kcal + 1.);
+    }
+}
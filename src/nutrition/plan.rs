@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use web_time::Instant;
+use super::{Food, Nutrient, NutrientKind, DietarySettings, portion_food, recommendable_foods, sum_nutrients, format_float};
+use super::optimise::{Constraints, optimise_diet};
+use super::goals::{CarbBudget, meal_carbohydrate_g};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Meal {
+    Breakfast,
+    Lunch,
+    Dinner,
+    Snacks,
+}
+
+pub const MEALS: [Meal; 4] = [
+    Meal::Breakfast,
+    Meal::Lunch,
+    Meal::Dinner,
+    Meal::Snacks,
+];
+
+pub fn meal_name(meal: Meal) -> &'static str {
+    match meal {
+        Meal::Breakfast => "🥣 Breakfast",
+        Meal::Lunch => "🥪 Lunch",
+        Meal::Dinner => "🍲 Dinner",
+        Meal::Snacks => "🍎 Snacks",
+    }
+}
+
+// how the day's energy is split between meals
+fn meal_share(meal: Meal) -> f32 {
+    match meal {
+        Meal::Breakfast => 0.25,
+        Meal::Lunch => 0.3,
+        Meal::Dinner => 0.35,
+        Meal::Snacks => 0.1,
+    }
+}
+
+// food groups it would be normal to eat at each meal
+fn meal_groups(meal: Meal) -> Vec<&'static str> {
+    match meal {
+        Meal::Breakfast => vec!["Cereals & bread", "Dairy", "Eggs", "Fruit", "Drinks", "Nuts & seeds"],
+        Meal::Lunch => vec![
+            "Cereals & bread", "Dishes", "Vegetables", "Beans & pulses", "Fish & seafood", "Eggs", "Dairy", "Fruit",
+        ],
+        Meal::Dinner => vec![
+            "Dishes", "Meat", "Fish & seafood", "Vegetables", "Beans & pulses", "Cereals & bread",
+        ],
+        Meal::Snacks => vec!["Fruit", "Nuts & seeds", "Dairy", "Sweets & desserts", "Drinks"],
+    }
+}
+
+// things to cook with rather than eat by the plateful
+const INGREDIENT_WORDS: [&str; 6] = ["powder", "flour", "granules", "essence", "oil", "icing"];
+
+fn is_ingredient(food: &Food) -> bool {
+    food.name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|w| INGREDIENT_WORDS.contains(&w))
+}
+
+// a planned meal should provide at least this much of its share of the
+// day's energy when it can
+const MIN_MEAL_ENERGY: f32 = 0.8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanSettings {
+    pub days: usize,
+    // the energy RI when not set
+    #[serde(default)]
+    pub daily_energy_kcal: Option<f32>,
+    pub foods_per_meal: usize,
+    // most times any one food appears over the whole plan
+    pub max_repeats: usize,
+}
+
+impl Default for PlanSettings {
+    fn default() -> Self {
+        PlanSettings {
+            days: 7,
+            daily_energy_kcal: None,
+            foods_per_meal: 3,
+            max_repeats: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedMeal {
+    pub meal: Meal,
+    // cofid names and grams, so that the plan can be saved and loaded again
+    pub foods: Vec<(String, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MealPlan {
+    pub days: Vec<Vec<PlannedMeal>>,
+}
+
+fn find_food<'a>(foods: &'a [Food], name: &str) -> Option<&'a Food> {
+    foods.iter().find(|f| f.name == name)
+}
+
+// the portions eaten on a day of the plan, ready to go in the diary
+pub fn day_foods(foods: &[Food], day: &[PlannedMeal]) -> Vec<Food> {
+    day
+        .iter()
+        .flat_map(|m| m.foods.iter())
+        .filter_map(|(name, grams)| find_food(foods, name).map(|f| portion_food(f, *grams)))
+        .collect::<Vec<Food>>()
+}
+
+pub fn daily_average(
+    nutrients: &[Nutrient],
+    foods: &[Food],
+    plan: &MealPlan,
+) -> HashMap<String, f32> {
+    let days = plan.days.len().max(1) as f32;
+    sum_nutrients(
+        nutrients.to_vec(),
+        plan.days.iter().flat_map(|d| day_foods(foods, d)).collect(),
+    )
+        .into_iter()
+        .map(|(n, x)| (n, x / days))
+        .collect::<HashMap<String, f32>>()
+}
+
+// when counting carbs, a meal's carbohydrate has to fit in its own budget
+// as well as what is left of the day's
+pub fn keep_to_carbs(
+    nutrients: Vec<Nutrient>,
+    nutrients_sum: &HashMap<String, f32>,
+    carbohydrate_g: Option<f32>,
+) -> Vec<Nutrient> {
+    let Some(carbohydrate_g) = carbohydrate_g else {
+        return nutrients;
+    };
+    nutrients
+        .into_iter()
+        .map(|n| match n.name.as_str() {
+            "carbohydrate_g" => Nutrient {
+                upper_limit: Some(
+                    n.upper_limit
+                        .unwrap_or(f32::INFINITY)
+                        .min(nutrients_sum[&n.name] + carbohydrate_g)
+                ),
+                ..n
+            },
+            _ => n,
+        })
+        .collect()
+}
+
+pub fn plan_energy(nutrients: &[Nutrient], plan_settings: &PlanSettings) -> f32 {
+    plan_settings.daily_energy_kcal.unwrap_or(
+        nutrients
+            .iter()
+            .find(|n| n.name == "energy_kcal")
+            .map_or(0., |n| n.recommended_intake)
+    )
+}
+
+// most time solving a whole plan in the app may take, shared out between
+// the meals still to plan. meals there is no time left for are left empty
+pub const PLAN_TIME_LIMIT: Duration = Duration::from_secs(60);
+
+// a plan worked out a meal at a time, so the app can show how far it has
+// got and let the page update in between
+pub struct Planner<'a> {
+    nutrients: Vec<Nutrient>,
+    pool: Vec<&'a Food>,
+    plan_settings: PlanSettings,
+    daily_energy_kcal: f32,
+    // each meal's carbohydrate when carbs are being counted
+    carbs: Option<CarbBudget>,
+    eaten: Vec<Food>,
+    // where today starts in eaten
+    day_start: usize,
+    times_used: HashMap<String, usize>,
+    days: Vec<Vec<PlannedMeal>>,
+    deadline: Option<Instant>,
+}
+
+pub fn start_plan<'a>(
+    nutrients: &[Nutrient],
+    foods: &'a [Food],
+    settings: &DietarySettings,
+    plan_settings: &PlanSettings,
+    carbs: Option<CarbBudget>,
+    time_limit: Option<Duration>,
+) -> Planner<'a> {
+    Planner {
+        nutrients: nutrients.to_vec(),
+        pool: recommendable_foods(foods, settings),
+        plan_settings: plan_settings.clone(),
+        daily_energy_kcal: plan_energy(nutrients, plan_settings),
+        carbs,
+        eaten: vec![],
+        day_start: 0,
+        times_used: HashMap::new(),
+        days: vec![],
+        deadline: time_limit.map(|t| Instant::now() + t),
+    }
+}
+
+// meals planned so far and how many there are to plan altogether
+pub fn plan_progress(planner: &Planner) -> (usize, usize) {
+    (
+        planner.days.iter().map(|d| d.len()).sum(),
+        planner.plan_settings.days * MEALS.len(),
+    )
+}
+
+// meals are planned one after another with the optimiser, each one
+// making up for what the week so far is short of, so that it is the
+// weekly average which meets the RIs rather than every single day.
+// false once every meal has been planned
+pub fn plan_next_meal(planner: &mut Planner) -> bool {
+    let (planned, total) = plan_progress(planner);
+    if planned >= total {
+        return false;
+    }
+    let day = planned / MEALS.len();
+    let i = planned % MEALS.len();
+    let meal = MEALS[i];
+    if i == 0 {
+        planner.days.push(vec![]);
+        planner.day_start = planner.eaten.len();
+    }
+    let nutrients = &planner.nutrients;
+    // each meal gets an equal share of the time left, split between the
+    // three solves of each of its two attempts below
+    let time_limit = planner.deadline.map(|d|
+        d.saturating_duration_since(Instant::now()) / (6 * (total - planned)) as u32
+    );
+    // so that breakfast can't use up the whole day's sugar, each meal
+    // only gets its share of what is left of the limits today
+    let share_left = MEALS[i..].iter().map(|m| meal_share(*m)).sum::<f32>();
+    let limit_share = meal_share(meal) / share_left;
+    // the sum the optimiser sees is what the week has eaten beyond
+    // the RIs of the days before this one
+    let week_sum = sum_nutrients(nutrients.clone(), planner.eaten.clone());
+    let nutrients_sum = nutrients
+        .iter()
+        .map(|n| {
+            let sum = week_sum[&n.name] - n.recommended_intake * day as f32;
+            let sum = match n.kind {
+                NutrientKind::Limit =>
+                    n.recommended_intake - (n.recommended_intake - sum) * limit_share,
+                _ => sum,
+            };
+            (n.name.clone(), sum)
+        })
+        .collect::<HashMap<String, f32>>();
+    // upper limits are about what is safe in a day, so unlike the
+    // RIs they don't carry over from one day to the next
+    let day_sum = sum_nutrients(nutrients.clone(), planner.eaten[planner.day_start..].to_vec());
+    let meal_nutrients = nutrients
+        .iter()
+        .map(|n| Nutrient {
+            upper_limit: n.upper_limit.map(|ul| ul - day_sum[&n.name] + nutrients_sum[&n.name]),
+            ..n.clone()
+        })
+        .collect::<Vec<Nutrient>>();
+    let meal_nutrients = keep_to_carbs(
+        meal_nutrients,
+        &nutrients_sum,
+        planner.carbs.map(|c| meal_carbohydrate_g(&c, meal)),
+    );
+    let groups = meal_groups(meal);
+    let planned_day = &planner.days[day];
+    let candidates = planner.pool
+        .iter()
+        .filter(|f| groups.contains(&f.group.as_str()) && !is_ingredient(f))
+        .filter(|f| planner.times_used.get(&f.name).is_none_or(|t| *t < planner.plan_settings.max_repeats))
+        .filter(|f| !planned_day
+            .iter()
+            .any(|m| m.foods.iter().any(|(name, _)| name == &f.name))
+        )
+        .copied()
+        .collect::<Vec<&Food>>();
+    let energy = planner.daily_energy_kcal * meal_share(meal);
+    let constraints = Constraints {
+        energy_budget_kcal: energy,
+        min_energy_kcal: energy * MIN_MEAL_ENERGY,
+        max_foods: planner.plan_settings.foods_per_meal,
+        typical_portions: true,
+        time_limit,
+        ..Constraints::default()
+    };
+    // the limits left for the week might not leave room for a
+    // filling meal, in which case a lighter one will have to do
+    let portions = if time_limit.is_some_and(|t| t.is_zero()) {
+        vec![]
+    } else {
+        optimise_diet(&meal_nutrients, &nutrients_sum, &candidates, &constraints)
+            .or_else(|_| optimise_diet(
+                &meal_nutrients,
+                &nutrients_sum,
+                &candidates,
+                &Constraints { min_energy_kcal: 0., ..constraints.clone() },
+            ))
+            .map_or(vec![], |o| o.portions)
+    };
+    for p in portions.iter() {
+        *planner.times_used.entry(p.food.name.clone()).or_insert(0) += 1;
+        planner.eaten.push(portion_food(p.food, p.grams));
+    }
+    planner.days[day].push(PlannedMeal {
+        meal,
+        foods: portions
+            .iter()
+            .map(|p| (p.food.name.clone(), p.grams))
+            .collect(),
+    });
+    planned + 1 < total
+}
+
+pub fn finish_plan(planner: Planner) -> MealPlan {
+    MealPlan { days: planner.days }
+}
+
+// the whole plan at once and without a time limit, so that it is the same
+// every time, for the cli and the tests
+pub fn generate_plan(
+    nutrients: &[Nutrient],
+    foods: &[Food],
+    settings: &DietarySettings,
+    plan_settings: &PlanSettings,
+    carbs: Option<CarbBudget>,
+) -> MealPlan {
+    let mut planner = start_plan(nutrients, foods, settings, plan_settings, carbs, None);
+    while plan_next_meal(&mut planner) {}
+    finish_plan(planner)
+}
+
+// grams of carbohydrate in a planned meal, for counting carbs
+pub fn meal_carbohydrate(foods: &[Food], meal: &PlannedMeal) -> f32 {
+    meal.foods
+        .iter()
+        .filter_map(|(name, grams)| find_food(foods, name).map(|f| f.nutrients["carbohydrate_g"] * grams / f.grams))
+        .sum()
+}
+
+// e.g. "Day 1\n  🥣 Breakfast: 50g of Cornflakes, 200g of Semi-skimmed Milk"
+pub fn plan_text(foods: &[Food], plan: &MealPlan) -> String {
+    plan.days
+        .iter()
+        .enumerate()
+        .map(|(i, day)| {
+            let meals = day
+                .iter()
+                .map(|m| {
+                    let items = m.foods
+                        .iter()
+                        .map(|(name, grams)| format!(
+                            "{}g of {}",
+                            format_float(*grams),
+                            find_food(foods, name).map_or(name.clone(), |f| f.display_name.clone()),
+                        ))
+                        .collect::<Vec<String>>();
+                    format!(
+                        "  {}: {}",
+                        meal_name(m.meal),
+                        if items.is_empty() { "nothing".to_string() } else { items.join(", ") },
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            format!("Day {}\n{meals}", i + 1)
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::super::{NutrientKind, DietarySettings};
+    use super::super::diet::{Diet, Tag};
+    use super::super::test_foods;
+
+    #[test]
+    fn meal_plan() -> () {
+        let (nutrients, foods) = test_foods();
+        let settings = DietarySettings {
+            diets: vec![Diet::Vegetarian],
+            ..DietarySettings::default()
+        };
+        let plan_settings = super::PlanSettings::default();
+        let plan = super::generate_plan(&nutrients, &foods, &settings, &plan_settings, None);
+
+        assert_eq!(plan.days.len(), 7);
+        let mut times_used = HashMap::<&String, usize>::new();
+        for day in plan.days.iter() {
+            assert_eq!(day.iter().map(|m| m.meal).collect::<Vec<_>>(), super::MEALS.to_vec());
+            for m in day.iter() {
+                assert!(m.foods.len() <= plan_settings.foods_per_meal);
+                for (name, _) in m.foods.iter() {
+                    *times_used.entry(name).or_insert(0) += 1;
+                }
+            }
+            let eaten = super::day_foods(&foods, day);
+            assert!(eaten.iter().all(|f| !f.tags.contains(&Tag::Meat) && !f.tags.contains(&Tag::Fish)));
+        }
+        assert!(times_used.values().all(|t| *t <= plan_settings.max_repeats));
+
+        let average = super::daily_average(&nutrients, &foods, &plan);
+        let energy = super::plan_energy(&nutrients, &plan_settings);
+        assert_eq!(energy, nutrients.iter().find(|n| n.name == "energy_kcal").unwrap().recommended_intake);
+        assert!(average["energy_kcal"] <= energy + 1.);
+        assert!(average["energy_kcal"] >= energy * 0.8);
+        // planned without a time limit, the week's average meets every target
+        for n in nutrients.iter().filter(|n| n.kind == NutrientKind::Target && n.recommended_intake > 0.1) {
+            assert!(average[&n.name] >= n.recommended_intake * 0.99, "{}", n.name);
+        }
+
+        let text = super::plan_text(&foods, &plan);
+        assert!(text.starts_with("Day 1\n  🥣 Breakfast: "));
+        assert_eq!(text.matches("Day ").count(), 7);
+    }
+
+    #[test]
+    fn counted_carbs() -> () {
+        let (nutrients, foods) = test_foods();
+        let preset = super::super::goals::Preset::CarbCounting;
+        let nutrients = super::super::goals::apply_preset(nutrients, &Some(preset));
+        let carbs = super::super::goals::preset_carb_budget(preset);
+        let plan_settings = super::PlanSettings { days: 1, ..super::PlanSettings::default() };
+        let plan = super::generate_plan(&nutrients, &foods, &DietarySettings::default(), &plan_settings, carbs);
+        for m in plan.days[0].iter() {
+            let budget = super::meal_carbohydrate_g(&carbs.expect("carbs are counted"), m.meal);
+            assert!(super::meal_carbohydrate(&foods, m) <= budget + 0.5, "{:?}", m.meal);
+        }
+        assert!(plan.days[0].iter().any(|m| !m.foods.is_empty()));
+    }
+}
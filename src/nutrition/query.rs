@@ -0,0 +1,512 @@
+use itertools::Itertools;
+use super::{Food, Nutrient, Basis, nutrient_amount};
+use super::filters::find_nutrient;
+
+
+// how many foods a query lists without a limit
+pub const DEFAULT_LIMIT: usize = 20;
+// most foods a query can list, since every one of them is shown
+pub const MAX_LIMIT: usize = 200;
+
+// a misspelt nutrient is only suggested when it's this close to a real one
+const MAX_SUGGESTION_EDITS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Word(String),
+    Symbol(&'static str),
+}
+
+// longest first so that "<=" isn't read as "<"
+const SYMBOLS: [&str; 13] = ["<=", ">=", "==", "!=", "<", ">", "=", "+", "-", "*", "/", "(", ")"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Less,
+    AtMost,
+    Greater,
+    AtLeast,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f32),
+    // amount per 100 g
+    Nutrient(String),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    condition: Option<Node>,
+    // what to sort by and whether it's biggest first
+    order: Option<(Node, bool)>,
+    limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    // in characters from the start of the query
+    pub position: usize,
+    pub message: String,
+}
+
+fn error<T>(position: usize, message: String) -> Result<T, QueryError> {
+    Err(QueryError { position, message })
+}
+
+fn lex(text: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest = &chars[i..];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let number = rest
+                .iter()
+                .copied()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect::<String>();
+            let value = number
+                .parse::<f32>()
+                .or(error(i, format!("{number} isn't a number")))?;
+            tokens.push((i, Token::Number(value)));
+            i += number.len();
+        } else if c.is_alphabetic() || c == '_' {
+            let word = rest
+                .iter()
+                .copied()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect::<String>();
+            i += word.chars().count();
+            tokens.push((i - word.chars().count(), Token::Word(word)));
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.iter().copied().take(s.len()).eq(s.chars())) {
+            tokens.push((i, Token::Symbol(symbol)));
+            i += symbol.len();
+        } else {
+            return error(i, format!("unexpected {c}"));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Tokens {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    // where the end of the query is, for errors about something missing
+    end: usize,
+}
+
+fn peek(tokens: &Tokens) -> Option<&Token> {
+    tokens.tokens.get(tokens.next).map(|(_, t)| t)
+}
+
+fn position(tokens: &Tokens) -> usize {
+    tokens.tokens.get(tokens.next).map_or(tokens.end, |(p, _)| *p)
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(Token::Number(x)) => format!("{x}"),
+        Some(Token::Word(w)) => w.clone(),
+        Some(Token::Symbol(s)) => s.to_string(),
+        None => "the end of the query".to_string(),
+    }
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+}
+
+// moves past the keyword if it's next
+fn take_keyword(tokens: &mut Tokens, keyword: &str) -> bool {
+    let found = is_keyword(peek(tokens), keyword);
+    if found {
+        tokens.next += 1;
+    }
+    found
+}
+
+fn take_symbol(tokens: &mut Tokens, symbols: &[(&str, Op)]) -> Option<Op> {
+    let op = match peek(tokens) {
+        Some(Token::Symbol(s)) => symbols.iter().find(|(symbol, _)| symbol == s).map(|(_, op)| *op),
+        _ => None,
+    };
+    if op.is_some() {
+        tokens.next += 1;
+    }
+    op
+}
+
+fn is_condition(node: &Node) -> bool {
+    match node {
+        Node::Not(_) => true,
+        Node::Binary(op, _, _) => !matches!(op, Op::Add | Op::Subtract | Op::Multiply | Op::Divide),
+        _ => false,
+    }
+}
+
+fn expect_number(node: &Node, position: usize, context: &str) -> Result<(), QueryError> {
+    match is_condition(node) {
+        true => error(position, format!("{context} needs a number, not a condition")),
+        false => Ok(()),
+    }
+}
+
+fn expect_condition(node: &Node, position: usize, context: &str) -> Result<(), QueryError> {
+    match is_condition(node) {
+        true => Ok(()),
+        false => error(position, format!("{context} needs a condition like protein_g > 10, not just a number")),
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + if ca == *cb { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn unknown_nutrient(nutrients: &[Nutrient], word: &str) -> String {
+    let suggestion = nutrients
+        .iter()
+        .map(|n| (edit_distance(&word.to_lowercase(), &n.name.to_lowercase()), &n.name))
+        .chain(nutrients.iter().filter_map(|n| n.name
+            .rsplit_once('_')
+            .map(|(stem, _)| (edit_distance(&word.to_lowercase(), &stem.to_lowercase()), &n.name))
+        ))
+        .filter(|(d, _)| *d <= MAX_SUGGESTION_EDITS)
+        .min_by_key(|(d, _)| *d);
+    match suggestion {
+        Some((_, name)) => format!("unknown nutrient {word}, did you mean {name}?"),
+        None => format!("unknown nutrient {word}"),
+    }
+}
+
+fn parse_atom(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    match peek(tokens).cloned() {
+        Some(Token::Number(x)) => {
+            tokens.next += 1;
+            Ok(Node::Number(x))
+        },
+        Some(Token::Symbol("(")) => {
+            tokens.next += 1;
+            let node = parse_or(nutrients, tokens)?;
+            match peek(tokens) {
+                Some(Token::Symbol(")")) => {
+                    tokens.next += 1;
+                    Ok(node)
+                },
+                other => error(
+                    position(tokens),
+                    format!("expected ) to close the ( at {}, found {}", start + 1, describe(other)),
+                ),
+            }
+        },
+        Some(Token::Word(w)) if !["and", "or", "not", "order", "limit"].contains(&w.to_lowercase().as_str()) => {
+            tokens.next += 1;
+            find_nutrient(nutrients, &w)
+                .map(|n| Node::Nutrient(n.name.clone()))
+                .ok_or(QueryError { position: start, message: unknown_nutrient(nutrients, &w) })
+        },
+        other => error(start, format!("expected a number or a nutrient, found {}", describe(other.as_ref()))),
+    }
+}
+
+fn parse_unary(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    if take_symbol(tokens, &[("-", Op::Subtract)]).is_some() {
+        let node = parse_unary(nutrients, tokens)?;
+        expect_number(&node, start, "-")?;
+        return Ok(Node::Negate(Box::new(node)));
+    }
+    parse_atom(nutrients, tokens)
+}
+
+// a left to right chain of one level of operators
+fn parse_chain(
+    nutrients: &Vec<Nutrient>,
+    tokens: &mut Tokens,
+    symbols: &[(&str, Op)],
+    operand: fn(&Vec<Nutrient>, &mut Tokens) -> Result<Node, QueryError>,
+) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    let mut node = operand(nutrients, tokens)?;
+    loop {
+        let symbol = describe(peek(tokens));
+        let Some(op) = take_symbol(tokens, symbols) else {
+            return Ok(node);
+        };
+        expect_number(&node, start, &symbol)?;
+        let right_start = position(tokens);
+        let right = operand(nutrients, tokens)?;
+        expect_number(&right, right_start, &symbol)?;
+        node = Node::Binary(op, Box::new(node), Box::new(right));
+    }
+}
+
+fn parse_product(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    parse_chain(nutrients, tokens, &[("*", Op::Multiply), ("/", Op::Divide)], parse_unary)
+}
+
+fn parse_sum(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    parse_chain(nutrients, tokens, &[("+", Op::Add), ("-", Op::Subtract)], parse_product)
+}
+
+const COMPARISONS: [(&str, Op); 7] = [
+    ("<", Op::Less),
+    ("<=", Op::AtMost),
+    (">", Op::Greater),
+    (">=", Op::AtLeast),
+    ("=", Op::Equal),
+    ("==", Op::Equal),
+    ("!=", Op::NotEqual),
+];
+
+fn parse_comparison(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    let left = parse_sum(nutrients, tokens)?;
+    let Some(op) = take_symbol(tokens, &COMPARISONS) else {
+        return Ok(left);
+    };
+    expect_number(&left, start, "a comparison")?;
+    let right_start = position(tokens);
+    let right = parse_sum(nutrients, tokens)?;
+    expect_number(&right, right_start, "a comparison")?;
+    if take_symbol(tokens, &COMPARISONS).is_some() {
+        return error(right_start, "comparisons can't be chained, join them with and".to_string());
+    }
+    Ok(Node::Binary(op, Box::new(left), Box::new(right)))
+}
+
+fn parse_not(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    if take_keyword(tokens, "not") {
+        let node = parse_not(nutrients, tokens)?;
+        expect_condition(&node, start, "not")?;
+        return Ok(Node::Not(Box::new(node)));
+    }
+    parse_comparison(nutrients, tokens)
+}
+
+// "and" binds tighter than "or"
+fn parse_logic(
+    nutrients: &Vec<Nutrient>,
+    tokens: &mut Tokens,
+    keyword: &str,
+    op: Op,
+    operand: fn(&Vec<Nutrient>, &mut Tokens) -> Result<Node, QueryError>,
+) -> Result<Node, QueryError> {
+    let start = position(tokens);
+    let mut node = operand(nutrients, tokens)?;
+    while take_keyword(tokens, keyword) {
+        expect_condition(&node, start, keyword)?;
+        let right_start = position(tokens);
+        let right = operand(nutrients, tokens)?;
+        expect_condition(&right, right_start, keyword)?;
+        node = Node::Binary(op, Box::new(node), Box::new(right));
+    }
+    Ok(node)
+}
+
+fn parse_and(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    parse_logic(nutrients, tokens, "and", Op::And, parse_not)
+}
+
+fn parse_or(nutrients: &Vec<Nutrient>, tokens: &mut Tokens) -> Result<Node, QueryError> {
+    parse_logic(nutrients, tokens, "or", Op::Or, parse_and)
+}
+
+// [condition] [order by number [asc|desc]] [limit n], with nutrients per
+// 100 g and n at most MAX_LIMIT, e.g. "protein_g / energy_kcal * 100 > 10
+// and sodium_mg < 200 order by fibre_g desc limit 20"
+pub fn parse_food_query(nutrients: &Vec<Nutrient>, text: &str) -> Result<Query, QueryError> {
+    let mut tokens = Tokens {
+        tokens: lex(text)?,
+        next: 0,
+        end: text.chars().count(),
+    };
+    let mut query = Query { condition: None, order: None, limit: DEFAULT_LIMIT };
+
+    take_keyword(&mut tokens, "where");
+    if peek(&tokens).is_some() && !is_keyword(peek(&tokens), "order") && !is_keyword(peek(&tokens), "limit") {
+        let start = position(&tokens);
+        let condition = parse_or(nutrients, &mut tokens)?;
+        expect_condition(&condition, start, "the query")?;
+        query.condition = Some(condition);
+    }
+    if take_keyword(&mut tokens, "order") {
+        if !take_keyword(&mut tokens, "by") {
+            return error(position(&tokens), format!("expected by after order, found {}", describe(peek(&tokens))));
+        }
+        let start = position(&tokens);
+        let order = parse_sum(nutrients, &mut tokens)?;
+        expect_number(&order, start, "order by")?;
+        // smallest first unless told otherwise
+        let descending = take_keyword(&mut tokens, "desc");
+        if !descending {
+            take_keyword(&mut tokens, "asc");
+        }
+        query.order = Some((order, descending));
+    }
+    if take_keyword(&mut tokens, "limit") {
+        query.limit = match peek(&tokens) {
+            Some(Token::Number(x)) if x.fract() == 0. && *x <= MAX_LIMIT as f32 => *x as usize,
+            Some(Token::Number(x)) if x.fract() == 0. => return error(
+                position(&tokens),
+                format!("limit can be at most {MAX_LIMIT}, not {x}"),
+            ),
+            other => return error(
+                position(&tokens),
+                format!("limit needs a whole number, found {}", describe(other)),
+            ),
+        };
+        tokens.next += 1;
+    }
+    match peek(&tokens) {
+        None => Ok(query),
+        other => error(
+            position(&tokens),
+            format!("expected and, or, order by or limit, found {}", describe(other)),
+        ),
+    }
+}
+
+// conditions are 1 when they hold and 0 when they don't, and dividing by
+// zero gives NaN, which fails every comparison and sorts last
+fn evaluate(node: &Node, food: &Food) -> f32 {
+    let truth = |b: bool| if b { 1. } else { 0. };
+    match node {
+        Node::Number(x) => *x,
+        Node::Nutrient(n) => nutrient_amount(food, n, Basis::Per100g),
+        Node::Negate(n) => -evaluate(n, food),
+        Node::Not(n) => truth(evaluate(n, food) == 0.),
+        Node::Binary(op, a, b) => {
+            let (a, b) = (evaluate(a, food), evaluate(b, food));
+            match op {
+                Op::Add => a + b,
+                Op::Subtract => a - b,
+                Op::Multiply => a * b,
+                Op::Divide => if b == 0. { f32::NAN } else { a / b },
+                Op::Less => truth(a < b),
+                Op::AtMost => truth(a <= b),
+                Op::Greater => truth(a > b),
+                Op::AtLeast => truth(a >= b),
+                Op::Equal => truth(a == b),
+                Op::NotEqual => truth(a != b),
+                Op::And => truth(a != 0. && b != 0.),
+                Op::Or => truth(a != 0. || b != 0.),
+            }
+        },
+    }
+}
+
+// positions of the foods the query picks with what they were sorted by
+pub fn run_query(query: &Query, foods: &[Food]) -> Vec<(usize, Option<f32>)> {
+    let picked = (0..foods.len())
+        .filter(|i| query.condition.as_ref().is_none_or(|c| evaluate(c, &foods[*i]) != 0.))
+        .map(|i| (i, query.order.as_ref().map(|(o, _)| evaluate(o, &foods[i]))));
+    match &query.order {
+        Some((_, descending)) => picked
+            .sorted_by(|(_, a), (_, b)| {
+                let (a, b) = (a.unwrap_or(f32::NAN), b.unwrap_or(f32::NAN));
+                match (a.is_nan(), b.is_nan(), descending) {
+                    (true, true, _) => std::cmp::Ordering::Equal,
+                    (true, false, _) => std::cmp::Ordering::Greater,
+                    (false, true, _) => std::cmp::Ordering::Less,
+                    (false, false, true) => b.total_cmp(&a),
+                    (false, false, false) => a.total_cmp(&b),
+                }
+            })
+            .take(query.limit)
+            .collect(),
+        None => picked.take(query.limit).collect(),
+    }
+}
+
+// the query with a caret under where it went wrong
+pub fn explain_error(text: &str, error: &QueryError) -> String {
+    format!("{text}\n{}^ {}", " ".repeat(error.position), error.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_foods;
+
+    #[test]
+    fn food_queries() -> () {
+        let (nutrients, foods) = test_foods();
+        let parse = |text: &str| super::parse_food_query(&nutrients, text);
+
+        let query = parse("protein_g / energy_kcal * 100 > 10 and sodium_mg < 200 order by fibre_g desc limit 20")
+            .expect("it parses");
+        let results = super::run_query(&query, &foods);
+        assert_eq!(results.len(), 20);
+        for (i, fibre) in results.iter() {
+            let f = &foods[*i].nutrients;
+            assert!(f["protein_g"] / f["energy_kcal"] * 100. > 10. && f["sodium_mg"] < 200.);
+            assert_eq!(*fibre, Some(f["fibre_g"]));
+        }
+        assert!(results.windows(2).all(|r| r[0].1 >= r[1].1));
+
+        // precedence, brackets, negation, nutrient aliases and defaults
+        let query = parse("not (protein > 20 or -fat_g >= -1) and 2 * (sugar_g + 1) <= 3").expect("it parses");
+        let results = super::run_query(&query, &foods);
+        assert_eq!(results.len(), super::DEFAULT_LIMIT);
+        for (i, order) in results.iter() {
+            let f = &foods[*i].nutrients;
+            assert!(!(f["protein_g"] > 20. || f["fat_g"] <= 1.) && f["sugar_g"] <= 0.5);
+            assert_eq!(*order, None);
+        }
+        let lowest = super::run_query(&parse("order by energy_kcal limit 3").expect("it parses"), &foods);
+        assert!(lowest.iter().all(|(_, kcal)| *kcal == Some(0.)));
+
+        // dividing by nothing never matches
+        let per_kcal = parse("protein_g / energy_kcal > 0").expect("it parses");
+        let results = super::run_query(&per_kcal, &foods);
+        assert!(results.iter().all(|(i, _)| foods[*i].nutrients["energy_kcal"] > 0.));
+
+        let error = |text: &str| parse(text).expect_err(text);
+        assert_eq!(error("protein_g >"), super::QueryError {
+            position: 11,
+            message: "expected a number or a nutrient, found the end of the query".to_string(),
+        });
+        assert_eq!(error("protien_g > 1").message, "unknown nutrient protien_g, did you mean protein_g?");
+        assert_eq!(error("fibre_g > 1 sodium_mg < 5").position, 12);
+        assert_eq!(error("fibre_g + 1").message, "the query needs a condition like protein_g > 10, not just a number");
+        assert_eq!(error("(fibre_g > 1").message, "expected ) to close the ( at 1, found the end of the query");
+        assert_eq!(error("1 < fibre_g < 2").message, "comparisons can't be chained, join them with and");
+        assert_eq!(error("order fibre_g").message, "expected by after order, found fibre_g");
+        assert_eq!(error("order by fibre_g > 1").position, 17);
+        assert_eq!(error("limit 2.5").message, "limit needs a whole number, found 2.5");
+        assert_eq!(error("limit 5000").message, "limit can be at most 200, not 5000");
+        assert_eq!(error("fibre_g # 2").message, "unexpected #");
+        assert_eq!(
+            super::explain_error("protein_g >", &error("protein_g >")),
+            "protein_g >\n           ^ expected a number or a nutrient, found the end of the query",
+        );
+    }
+}
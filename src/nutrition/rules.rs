@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use regex::Regex;
+use super::diet::{Tag, parse_tag};
+
+
+enum Pattern {
+    // whole words or phrases, normalised the same way as the food's name
+    Keywords {
+        words: HashSet<String>,
+        phrases: Vec<String>,
+    },
+    Regex(Regex),
+}
+
+enum Field {
+    Emoji(String),
+    Group(String),
+    Tag(Tag),
+}
+
+struct Rule {
+    field: Field,
+    pattern: Pattern,
+    unless: Option<Pattern>,
+}
+
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub emoji: String,
+    pub group: String,
+    pub tags: Vec<Tag>,
+}
+
+// lowercase words separated by single spaces and padded at either end
+fn normalise_words(text: &str) -> String {
+    let words = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    format!(" {words} ")
+}
+
+fn parse_pattern(s: &str) -> Result<Option<Pattern>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    if s.len() > 1 && s.starts_with('/') && s.ends_with('/') {
+        return Regex::new(&format!("(?i){}", &s[1..s.len() - 1]))
+            .map(|r| Some(Pattern::Regex(r)))
+            .map_err(|e| format!("bad regex {s}: {e}"));
+    }
+    let (phrases, words): (Vec<String>, Vec<String>) = s
+        .split('|')
+        .map(normalise_words)
+        .filter(|w| !w.trim().is_empty())
+        .partition(|w| w.trim().contains(' '));
+    Ok(Some(Pattern::Keywords {
+        words: words.iter().map(|w| w.trim().to_owned()).collect(),
+        phrases,
+    }))
+}
+
+fn parse_rule(record: csv::StringRecord) -> Result<Rule, String> {
+    let get = |i: usize| record.get(i).unwrap_or("").trim();
+    let field = match get(0) {
+        "emoji" => Field::Emoji(get(1).to_owned()),
+        "group" => Field::Group(get(1).to_owned()),
+        "tag" => Field::Tag(
+            parse_tag(get(1)).ok_or(format!("unknown tag {}", get(1)))?
+        ),
+        other => return Err(format!("unknown rule field {other}")),
+    };
+    Ok(Rule {
+        field,
+        pattern: parse_pattern(get(2))?.ok_or(format!("rule for {} has no pattern", get(1)))?,
+        unless: parse_pattern(get(3))?,
+    })
+}
+
+pub fn parse_rules(csv: &str) -> Result<Rules, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let rules = reader
+        .records()
+        .map(|r| parse_rule(r.map_err(|e| e.to_string())?))
+        .collect::<Result<Vec<Rule>, String>>()?;
+    Ok(Rules { rules })
+}
+
+pub fn default_rules() -> &'static Rules {
+    static RULES: OnceLock<Rules> = OnceLock::new();
+    RULES.get_or_init(|| parse_rules(
+        include_str!("../../assets/rules.csv")
+    ).expect("rules.csv is error free"))
+}
+
+struct Text {
+    raw: String,
+    words: String,
+}
+
+fn matches(pattern: &Pattern, text: &Text) -> bool {
+    match pattern {
+        Pattern::Keywords { words, phrases } =>
+            text.words.split_whitespace().any(|w| words.contains(w))
+                || phrases.iter().any(|p| text.words.contains(p.as_str())),
+        Pattern::Regex(regex) => regex.is_match(&text.raw),
+    }
+}
+
+// an emoji which is already known is kept, saving a trip through the emoji rules
+pub fn classify(
+    rules: &Rules,
+    name: &str,
+    display_name: &str,
+    known_emoji: &str,
+) -> Classification {
+    let raw = format!("{display_name}, {name}");
+    let text = Text {
+        words: normalise_words(&raw),
+        raw,
+    };
+    let mut classification = Classification {
+        emoji: known_emoji.to_string(),
+        group: "Other".to_string(),
+        tags: vec![],
+    };
+    let (mut emoji_found, mut group_found) = (!known_emoji.is_empty(), false);
+    for rule in rules.rules.iter() {
+        let wanted = match rule.field {
+            Field::Emoji(_) => !emoji_found,
+            Field::Group(_) => !group_found,
+            Field::Tag(tag) => !classification.tags.contains(&tag),
+        };
+        if !wanted
+            || !matches(&rule.pattern, &text)
+            || rule.unless.as_ref().is_some_and(|u| matches(u, &text)) {
+            continue;
+        }
+        match &rule.field {
+            Field::Emoji(emoji) => {
+                classification.emoji = emoji.clone();
+                emoji_found = true;
+            },
+            Field::Group(group) => {
+                classification.group = group.clone();
+                group_found = true;
+            },
+            Field::Tag(tag) => classification.tags.push(*tag),
+        }
+    }
+    classification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tag;
+
+    fn classify(name: &str) -> super::Classification {
+        super::classify(super::default_rules(), name, name, "")
+    }
+
+    #[test]
+    fn tags() -> () {
+        assert_eq!(classify("Beef, mince, stewed").tags, vec![Tag::Meat]);
+        assert_eq!(classify("Burgers, vegetarian, grilled").tags, vec![]);
+        assert_eq!(classify("Beans, red kidney, canned").tags, vec![]);
+        assert_eq!(classify("Peanut butter, smooth").tags, vec![Tag::Nuts]);
+        assert_eq!(classify("Milk, soya, unsweetened").tags, vec![Tag::Soy]);
+        assert_eq!(classify("Haddock, in batter, fried").tags, vec![Tag::Fish, Tag::Gluten]);
+        assert_eq!(classify("Doughnuts, jam").tags, vec![Tag::Gluten]);
+    }
+
+    #[test]
+    fn emojis_and_groups() -> () {
+        let baguette = classify("Homemade garlic baguette");
+        assert_eq!(baguette.emoji, "🥖");
+        assert_eq!(baguette.group, "Cereals & bread");
+
+        let carrots = classify("Carrots, boiled");
+        assert_eq!(carrots.emoji, "🥕");
+        assert_eq!(carrots.group, "Vegetables");
+
+        let mystery = classify("Zzyzx");
+        assert_eq!(mystery.emoji, "🍛");
+        assert_eq!(mystery.group, "Other");
+    }
+
+    #[test]
+    fn custom_rules() -> () {
+        let rules = super::parse_rules(
+            "field,value,pattern,unless\n\
+             # comments are ignored\n\
+             emoji,🐉,/drag[aeo]n ?fruit/,\n\
+             group,Exotic,dragon fruit|pitaya,dried\n"
+        ).expect("rules parse");
+        let fruit = super::classify(&rules, "Dragonfruit, raw", "Fresh Dragonfruit", "");
+        assert_eq!(fruit.emoji, "🐉");
+        assert_eq!(fruit.group, "Other");
+        let pitaya = super::classify(&rules, "Pitaya", "Pitaya", "🌵");
+        assert_eq!(pitaya.group, "Exotic");
+        assert_eq!(pitaya.emoji, "🌵");
+
+        assert!(super::parse_rules("field,value,pattern,unless\nflavour,x,y,\n").is_err());
+        assert!(super::parse_rules("field,value,pattern,unless\nemoji,x,/(/,\n").is_err());
+    }
+}
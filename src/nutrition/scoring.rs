@@ -0,0 +1,219 @@
+use serde::{Serialize, Deserialize};
+use super::{Food, Nutrient, NutrientKind, per_100_kcal};
+
+
+// scores one nutrient of a food given how much of it has been eaten so far,
+// a food's score is the sum over all nutrients with an RI
+pub trait Scorer {
+    fn nutrient_score(&self, nutrient: &Nutrient, food: &Food, sum: f32) -> i64;
+}
+
+pub fn overage_penalty(value: f32, sum: f32, limit: f32) -> i64 {
+    // only the part of this food which goes over the limit counts
+    let over = (sum + value - limit).max(0.).min(value);
+    -(( 4000. * over / limit ) as i64).min(1000)
+}
+
+// the original formula, rewards targets less and less as they fill up
+pub struct Balanced;
+
+impl Scorer for Balanced {
+    fn nutrient_score(&self, nutrient: &Nutrient, food: &Food, sum: f32) -> i64 {
+        let value = food.nutrients[&nutrient.name];
+        match nutrient.kind {
+            NutrientKind::Target => {
+                let score = ((( 1000. * value / nutrient.recommended_intake ) * ( 1. - 4. * sum / nutrient.recommended_intake )) as i64).min(1000).max(-1000);
+                score + nutrient.upper_limit.map_or(0, |ul| overage_penalty(value, sum, ul))
+            },
+            NutrientKind::Limit =>
+                overage_penalty(value, sum, nutrient.recommended_intake),
+            NutrientKind::Informational => 0,
+        }
+    }
+}
+
+// only the part of a target which is still missing counts, and counts for
+// more the more of it is missing
+pub struct DeficitWeighted;
+
+impl Scorer for DeficitWeighted {
+    fn nutrient_score(&self, nutrient: &Nutrient, food: &Food, sum: f32) -> i64 {
+        let value = food.nutrients[&nutrient.name];
+        match nutrient.kind {
+            NutrientKind::Target => {
+                let deficit = (nutrient.recommended_intake - sum).max(0.);
+                let filled = value.min(deficit) / nutrient.recommended_intake;
+                let score = ( 1000. * filled * deficit / nutrient.recommended_intake ) as i64;
+                score + nutrient.upper_limit.map_or(0, |ul| overage_penalty(value, sum, ul))
+            },
+            NutrientKind::Limit =>
+                overage_penalty(value, sum, nutrient.recommended_intake),
+            NutrientKind::Informational => 0,
+        }
+    }
+}
+
+// the original formula per 100 kcal rather than per 100 g, which gives
+// vegetables a fair go against dried and concentrated foods
+pub struct EnergyNormalised;
+
+impl Scorer for EnergyNormalised {
+    fn nutrient_score(&self, nutrient: &Nutrient, food: &Food, sum: f32) -> i64 {
+        ( Balanced.nutrient_score(nutrient, food, sum) as f32 * per_100_kcal(food) ) as i64
+    }
+}
+
+// how nutrient rich the food is per 100 kcal whatever has been eaten, each
+// target capped at a full RI and each limit counting against it
+pub struct NutrientDensity;
+
+impl Scorer for NutrientDensity {
+    fn nutrient_score(&self, nutrient: &Nutrient, food: &Food, _sum: f32) -> i64 {
+        let share = food.nutrients[&nutrient.name] / nutrient.recommended_intake;
+        let score = match nutrient.kind {
+            NutrientKind::Target => 1000. * share.min(1.) * per_100_kcal(food),
+            NutrientKind::Limit => -1000. * share * per_100_kcal(food),
+            NutrientKind::Informational => 0.,
+        };
+        (score as i64).clamp(-1000, 1000)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ScorerKind {
+    #[default]
+    Balanced,
+    DeficitWeighted,
+    EnergyNormalised,
+    NutrientDensity,
+}
+
+pub const SCORERS: [ScorerKind; 4] = [
+    ScorerKind::Balanced,
+    ScorerKind::DeficitWeighted,
+    ScorerKind::EnergyNormalised,
+    ScorerKind::NutrientDensity,
+];
+
+pub fn scorer(kind: ScorerKind) -> &'static dyn Scorer {
+    match kind {
+        ScorerKind::Balanced => &Balanced,
+        ScorerKind::DeficitWeighted => &DeficitWeighted,
+        ScorerKind::EnergyNormalised => &EnergyNormalised,
+        ScorerKind::NutrientDensity => &NutrientDensity,
+    }
+}
+
+// also what the cli accepts after --scorer
+pub fn scorer_id(kind: ScorerKind) -> &'static str {
+    match kind {
+        ScorerKind::Balanced => "balanced",
+        ScorerKind::DeficitWeighted => "deficit",
+        ScorerKind::EnergyNormalised => "energy",
+        ScorerKind::NutrientDensity => "density",
+    }
+}
+
+pub fn parse_scorer(s: &str) -> Option<ScorerKind> {
+    SCORERS
+        .iter()
+        .find(|k| scorer_id(**k) == s)
+        .copied()
+}
+
+pub fn scorer_name(kind: ScorerKind) -> &'static str {
+    match kind {
+        ScorerKind::Balanced => "⚖️ Balanced",
+        ScorerKind::DeficitWeighted => "🕳️ Fill the gaps",
+        ScorerKind::EnergyNormalised => "🔥 Per calorie",
+        ScorerKind::NutrientDensity => "💎 Nutrient dense",
+    }
+}
+
+pub fn scorer_description(kind: ScorerKind) -> &'static str {
+    match kind {
+        ScorerKind::Balanced =>
+            "Rewards foods rich in whatever you are low on, per 100 grams.",
+        ScorerKind::DeficitWeighted =>
+            "Only counts what a food adds towards targets you haven't reached, favouring the biggest gaps.",
+        ScorerKind::EnergyNormalised =>
+            "Like balanced, but per 100 kcal so that light foods such as vegetables get a fair go.",
+        ScorerKind::NutrientDensity =>
+            "Rewards foods packed with nutrients for their calories, whatever you have eaten so far.",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::super::{Food, Nutrient, NutrientKind, new_food};
+    use super::{ScorerKind, SCORERS, scorer, parse_scorer, scorer_id};
+
+    fn nutrient(name: &str, recommended_intake: f32, kind: NutrientKind, upper_limit: Option<f32>) -> Nutrient {
+        Nutrient {
+            name: name.to_string(),
+            display_name: name.to_string(),
+            abbreviation: name.to_string(),
+            units: "".to_string(),
+            recommended_intake,
+            kind,
+            upper_limit,
+        }
+    }
+
+    fn food(name: &str, iron: f32, sodium: f32, energy: f32) -> Food {
+        new_food(
+            name.to_string(),
+            name.to_string(),
+            "🍽️".to_string(),
+            true,
+            HashMap::from([
+                ("iron_mg".to_string(), iron),
+                ("sodium_mg".to_string(), sodium),
+                ("energy_kcal".to_string(), energy),
+            ]),
+        )
+    }
+
+    fn score(kind: ScorerKind, nutrients: &[Nutrient], food: &Food, sums: [f32; 3]) -> i64 {
+        nutrients
+            .iter()
+            .zip(sums)
+            .map(|(n, sum)| scorer(kind).nutrient_score(n, food, sum))
+            .sum()
+    }
+
+    #[test]
+    fn scorers() -> () {
+        let nutrients = vec![
+            nutrient("iron_mg", 10., NutrientKind::Target, Some(40.)),
+            nutrient("sodium_mg", 2000., NutrientKind::Limit, None),
+            nutrient("energy_kcal", 2000., NutrientKind::Limit, None),
+        ];
+        let spinach = food("Spinach", 2., 50., 25.);
+        let liver = food("Liver", 8., 80., 150.);
+        let crisps = food("Crisps", 1., 800., 500.);
+
+        // per 100 g liver wins, per calorie spinach does
+        let fresh = [0., 0., 0.];
+        let scores = |kind: ScorerKind, sums: [f32; 3]| [&spinach, &liver, &crisps]
+            .map(|f| score(kind, &nutrients, f, sums));
+        assert_eq!(scores(ScorerKind::Balanced, fresh), [200, 800, 100]);
+        assert_eq!(scores(ScorerKind::DeficitWeighted, fresh), [200, 800, 100]);
+        assert_eq!(scores(ScorerKind::EnergyNormalised, fresh), [800, 533, 20]);
+        assert_eq!(scores(ScorerKind::NutrientDensity, fresh), [650, 457, -110]);
+
+        // with most of the iron eaten and sodium nearly at its limit, only
+        // the deficit weighted scorer stops counting iron against foods
+        let late = [9., 1990., 1500.];
+        assert_eq!(scores(ScorerKind::Balanced, late), [-600, -1140, -1260]);
+        assert_eq!(scores(ScorerKind::DeficitWeighted, late), [-70, -130, -990]);
+        assert_eq!(scores(ScorerKind::EnergyNormalised, late), [-2400, -759, -252]);
+        assert_eq!(scores(ScorerKind::NutrientDensity, late), scores(ScorerKind::NutrientDensity, fresh));
+
+        for kind in SCORERS {
+            assert_eq!(parse_scorer(scorer_id(kind)), Some(kind));
+        }
+        assert_eq!(parse_scorer("tasty"), None);
+    }
+}
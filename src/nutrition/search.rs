@@ -0,0 +1,593 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use itertools::Itertools;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Serialize, Deserialize};
+use super::Food;
+
+
+// how many results a search gives
+pub const SEARCH_RESULTS: usize = 20;
+
+// results scoring less than this fraction of what the search would score
+// against itself are too far off to show
+const MIN_RELEVANCE: f32 = 0.4;
+
+// a match on the cofid name only counts for this fraction of one on the
+// display name, so it finds foods without pushing aside better named ones
+const NAME_MATCH_DIVISOR: i64 = 2;
+
+// a food eaten every day scores this much more than its name alone would
+// give it, unless changed in the settings
+const DEFAULT_PERSONALISATION: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    // how much the foods someone logs count for in their searches, 0 is off
+    pub personalisation: f32,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings { personalisation: DEFAULT_PERSONALISATION }
+    }
+}
+
+// what someone eats, to rank their usual foods higher, the default is the
+// plain ranking by name
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Usage {
+    // how much of the time each food is eaten by cofid name, from 0 to 1
+    pub foods: HashMap<String, f32>,
+    pub weight: f32,
+}
+
+// other words for what is in the food database, keyed by the words searched
+// for with the same normalising as tokens
+pub struct Synonyms {
+    means: HashMap<String, Vec<String>>,
+    // only when they are the whole search, for words that mean something
+    // else in a longer name, like the soda in soda bread
+    whole: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchMatch {
+    // position in the foods searched
+    pub id: usize,
+    // what the matches are ordered by, personalised by how often the food
+    // is eaten
+    pub score: i64,
+    // characters of the display name which matched, none when it was the
+    // cofid name which matched
+    pub indices: Vec<usize>,
+}
+
+// a search result standing for every way of preparing the same food
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantGroup {
+    pub best: SearchMatch,
+    // positions of all the allowed variants and how each is prepared,
+    // including the best match
+    pub variants: Vec<(usize, String)>,
+}
+
+// built once when the foods are loaded so that each keystroke only has to
+// fuzzy match the foods which share some letters with the search
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchIndex {
+    // the base food and how it's prepared for each food, by position
+    variants: Vec<(String, String)>,
+    // foods with the same base food
+    bases: HashMap<String, Vec<usize>>,
+    // foods with a word starting with each one or two letters
+    prefixes: HashMap<String, Vec<usize>>,
+    // foods with a word containing each three letters
+    trigrams: HashMap<String, Vec<usize>>,
+}
+
+// lowercase words, so "Semi-skimmed Milk" is "semi", "skimmed" and "milk"
+pub fn tokens(text: &str) -> Vec<String> {
+    text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect()
+}
+
+fn normalise(text: &str) -> String {
+    tokens(text).join(" ")
+}
+
+pub fn parse_synonyms(csv: &str) -> Result<Synonyms, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let mut means = HashMap::<String, Vec<String>>::new();
+    let mut whole = HashMap::<String, Vec<String>>::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let raw = record.get(0).unwrap_or("").trim();
+        let search = normalise(raw);
+        let meaning = record
+            .get(1)
+            .unwrap_or("")
+            .split('|')
+            .map(normalise)
+            .filter(|m| !m.is_empty())
+            .collect::<Vec<String>>();
+        if search.is_empty() || meaning.is_empty() {
+            return Err(format!("synonym {search} needs a search and what it means"));
+        }
+        match raw.starts_with('=') {
+            true => whole.entry(search).or_default().extend(meaning),
+            false => means.entry(search).or_default().extend(meaning),
+        }
+    }
+    Ok(Synonyms { means, whole })
+}
+
+pub fn default_synonyms() -> &'static Synonyms {
+    static SYNONYMS: OnceLock<Synonyms> = OnceLock::new();
+    SYNONYMS.get_or_init(|| parse_synonyms(
+        include_str!("../../assets/synonyms.csv")
+    ).expect("synonyms.csv is error free"))
+}
+
+// the search as typed followed by the search with each synonym swapped in,
+// so "zucchini soup" is also searched for as "courgette soup"
+pub fn expand(synonyms: &Synonyms, search: &str) -> Vec<String> {
+    let search = search.trim().to_lowercase();
+    let padded = format!(" {} ", normalise(&search));
+    let mut searches = vec![search];
+    if let Some(meaning) = synonyms.whole.get(padded.trim()) {
+        searches.extend(meaning.iter().cloned());
+    }
+    for (word, meaning) in synonyms.means.iter().sorted() {
+        if !padded.contains(&format!(" {word} ")) {
+            continue;
+        }
+        for m in meaning {
+            let swapped = padded.replacen(&format!(" {word} "), &format!(" {m} "), 1);
+            searches.push(swapped.trim().to_string());
+        }
+    }
+    searches.into_iter().unique().collect()
+}
+
+fn trigrams(word: &str) -> Vec<String> {
+    word
+        .chars()
+        .collect::<Vec<char>>()
+        .windows(3)
+        .map(|w| w.iter().collect::<String>())
+        .collect()
+}
+
+fn prefixes(word: &str) -> Vec<String> {
+    let chars = word.chars().collect::<Vec<char>>();
+    (1..=chars.len().min(2))
+        .map(|n| chars[..n].iter().collect())
+        .collect()
+}
+
+// words which start the part of a cofid name saying how the food was
+// prepared rather than what it is
+const PREPARATION_WORDS: [&str; 37] = [
+    "raw", "boiled", "fried", "stir", "deep", "dry", "canned", "frozen", "baked", "roasted", "roast",
+    "grilled", "steamed", "microwaved", "stewed", "poached", "mashed", "dried", "smoked", "casseroled",
+    "barbecued", "braised", "toasted", "pasteurised", "sterilised", "uht", "cooked", "re", "reheated",
+    "heated", "drained", "homemade", "retail", "takeaway", "purchased", "chilled", "fresh",
+];
+
+fn is_preparation(part: &str) -> bool {
+    tokens(part).first().is_some_and(|w| PREPARATION_WORDS.contains(&w.as_str()))
+}
+
+// the cofid name split where the preparation starts, so "Carrots, young,
+// canned in water, re-heated, drained" is the base food "carrots, young"
+// prepared "canned in water, re-heated, drained"
+pub fn split_variant(name: &str) -> (String, String) {
+    let parts = name.split(',').map(|p| p.trim()).collect::<Vec<&str>>();
+    let base_len = parts
+        .iter()
+        .skip(1)
+        .position(|p| is_preparation(p))
+        .map_or(parts.len(), |i| i + 1);
+    (parts[..base_len].join(", ").to_lowercase(), parts[base_len..].join(", "))
+}
+
+pub fn build_index(foods: &[Food]) -> SearchIndex {
+    let mut index = SearchIndex {
+        variants: foods.iter().map(|f| split_variant(&f.name)).collect(),
+        bases: HashMap::new(),
+        prefixes: HashMap::new(),
+        trigrams: HashMap::new(),
+    };
+    for (i, (base, _)) in index.variants.iter().enumerate() {
+        index.bases.entry(base.clone()).or_default().push(i);
+    }
+    for (i, food) in foods.iter().enumerate() {
+        let words = tokens(&format!("{} {}", food.display_name, food.name));
+        for p in words.iter().flat_map(|w| prefixes(w)).unique() {
+            index.prefixes.entry(p).or_default().push(i);
+        }
+        for t in words.iter().flat_map(|w| trigrams(w)).unique() {
+            index.trigrams.entry(t).or_default().push(i);
+        }
+    }
+    index
+}
+
+// foods which could match a search word: short words have to start one of
+// the food's words and longer ones have to share at least half their
+// trigrams with it, which lets a typo or two through
+fn word_candidates(index: &SearchIndex, word: &str) -> HashSet<usize> {
+    let word_trigrams = trigrams(word);
+    if word_trigrams.is_empty() {
+        return index.prefixes.get(word).map_or(HashSet::new(), |f| f.iter().copied().collect());
+    }
+    let mut shared = HashMap::<usize, usize>::new();
+    for t in word_trigrams.iter().unique() {
+        for i in index.trigrams.get(t).into_iter().flatten() {
+            *shared.entry(*i).or_insert(0) += 1;
+        }
+    }
+    shared
+        .into_iter()
+        .filter(|(_, n)| n * 2 >= word_trigrams.len())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// positions of the foods which could match every word of the search, or
+// None when nothing does and the search may be an abbreviation like "chdr"
+// which only the fuzzy matcher can make sense of
+pub fn candidates(index: &SearchIndex, search: &str) -> Option<Vec<usize>> {
+    tokens(search)
+        .iter()
+        .map(|w| word_candidates(index, w))
+        .reduce(|a, b| a.intersection(&b).copied().collect())
+        .filter(|c| !c.is_empty())
+        .map(|c| c.into_iter().sorted().collect())
+}
+
+// how well the food matches any of the searches, by its display name or,
+// less strongly, its cofid name
+fn score(matcher: &SkimMatcherV2, food: &Food, searches: &[String]) -> i64 {
+    searches
+        .iter()
+        .map(|s| {
+            let display = matcher.fuzzy_match(&food.display_name, s).unwrap_or(0);
+            let name = matcher.fuzzy_match(&food.name, s).unwrap_or(0);
+            display.max(name / NAME_MATCH_DIVISOR)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// the score raised by how often the food is eaten, so a usual food comes
+// before others which match about as well without jumping ahead of much
+// better matches
+fn personalise(score: i64, food: &Food, usage: &Usage) -> i64 {
+    let eaten = usage.foods.get(&food.name).unwrap_or(&0.);
+    score + (score as f32 * usage.weight * eaten).round() as i64
+}
+
+// the best fuzzy matches for the search and its synonyms with their
+// personalised scores, preferring shorter names when the score is the same and then
+// alphabetical ones, so that the order doesn't depend on which foods were
+// searched through. matches too far off to show are left out
+pub fn rank<'a, T>(
+    items: impl Iterator<Item = T>,
+    food: impl Fn(&T) -> &'a Food,
+    search: &str,
+    usage: &Usage,
+) -> Vec<(T, i64)> {
+    rank_above(items, food, search, usage, MIN_RELEVANCE)
+}
+
+// the same, keeping every match however weak, for looking up the closest
+// food to a name rather than showing results
+pub fn rank_all<'a, T>(
+    items: impl Iterator<Item = T>,
+    food: impl Fn(&T) -> &'a Food,
+    search: &str,
+    usage: &Usage,
+) -> Vec<(T, i64)> {
+    rank_above(items, food, search, usage, 0.)
+}
+
+fn rank_above<'a, T>(
+    items: impl Iterator<Item = T>,
+    food: impl Fn(&T) -> &'a Food,
+    search: &str,
+    usage: &Usage,
+    min_relevance: f32,
+) -> Vec<(T, i64)> {
+    let matcher = SkimMatcherV2::default();
+    let searches = expand(default_synonyms(), search);
+    let relevant = matcher.fuzzy_match(&searches[0], &searches[0]).unwrap_or(0) as f32 * min_relevance;
+    items
+        .map(|i| {
+            let s = score(&matcher, food(&i), &searches);
+            (i, s)
+        })
+        .filter(|(_, s)| *s > 0 && *s as f32 >= relevant)
+        .map(|(i, s)| {
+            let s = personalise(s, food(&i), usage);
+            (i, s)
+        })
+        .k_largest_by_key(
+            SEARCH_RESULTS,
+            |(i, s)| {
+                let f = food(i);
+                (*s, Reverse(f.display_name.len()), Reverse((&f.display_name, &f.name)))
+            }
+        )
+        .collect()
+}
+
+// which characters of the display name to highlight, from the search or
+// synonym which matched it best, unless the cofid name matched better
+fn matched_indices(matcher: &SkimMatcherV2, food: &Food, searches: &[String]) -> Vec<usize> {
+    let name = searches
+        .iter()
+        .map(|s| matcher.fuzzy_match(&food.name, s).unwrap_or(0) / NAME_MATCH_DIVISOR)
+        .max()
+        .unwrap_or(0);
+    searches
+        .iter()
+        .filter_map(|s| matcher.fuzzy_indices(&food.display_name, s))
+        .max_by_key(|(s, _)| *s)
+        .filter(|(s, _)| *s >= name)
+        .map_or(vec![], |(_, indices)| indices)
+}
+
+// the best matches which are allowed, most relevant first
+pub fn search_foods(
+    index: &SearchIndex,
+    foods: &[Food],
+    search: &str,
+    allowed: impl Fn(&Food) -> bool,
+    usage: &Usage,
+) -> Vec<SearchMatch> {
+    let searches = expand(default_synonyms(), search);
+    let ids = searches
+        .iter()
+        .filter_map(|s| candidates(index, s))
+        .reduce(|a, b| a.into_iter().chain(b).sorted().dedup().collect())
+        .unwrap_or((0..foods.len()).collect());
+    let matcher = SkimMatcherV2::default();
+    rank(ids.into_iter().filter(|i| allowed(&foods[*i])), |i| &foods[*i], search, usage)
+        .into_iter()
+        .map(|(i, score)| SearchMatch {
+            id: i,
+            score,
+            indices: matched_indices(&matcher, &foods[i], &searches),
+        })
+        .collect()
+}
+
+// matches for the same base food are folded into the best of them, which
+// offers all the food's allowed variants to pick from
+pub fn group_variants(
+    index: &SearchIndex,
+    foods: &[Food],
+    matches: Vec<SearchMatch>,
+    allowed: impl Fn(&Food) -> bool,
+) -> Vec<VariantGroup> {
+    matches
+        .into_iter()
+        .unique_by(|m| index.variants[m.id].0.clone())
+        .map(|m| {
+            let variants = index.bases[&index.variants[m.id].0]
+                .iter()
+                .filter(|i| **i == m.id || allowed(&foods[**i]))
+                .map(|i| (*i, index.variants[*i].1.clone()))
+                .sorted_by(|(_, a), (_, b)| a.cmp(b))
+                .collect();
+            VariantGroup { best: m, variants }
+        })
+        .collect()
+}
+
+// the text split into runs which are highlighted or not
+pub fn highlight(text: &str, indices: &[usize]) -> Vec<(String, bool)> {
+    text
+        .chars()
+        .enumerate()
+        .chunk_by(|(i, _)| indices.contains(i))
+        .into_iter()
+        .map(|(matched, chars)| (chars.map(|(_, c)| c).collect(), matched))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::super::Food;
+    use super::Usage;
+    use super::super::test_foods;
+
+    fn names(foods: Vec<&Food>) -> Vec<String> {
+        foods.iter().map(|f| f.name.clone()).collect()
+    }
+
+    fn everything<'a>(foods: &'a [Food], search: &str) -> Vec<&'a Food> {
+        super::rank(foods.iter(), |f| *f, search, &Usage::default()).into_iter().map(|(f, _)| f).collect()
+    }
+
+    fn found<'a>(index: &super::SearchIndex, foods: &'a [Food], search: &str) -> Vec<&'a Food> {
+        super::search_foods(index, foods, search, |_| true, &Usage::default()).into_iter().map(|m| &foods[m.id]).collect()
+    }
+
+    #[test]
+    fn search_index() -> () {
+        let foods = test_foods().1;
+        let index = super::build_index(&foods);
+        assert_eq!(super::tokens("Semi-skimmed Milk, UHT"), vec!["semi", "skimmed", "milk", "uht"]);
+
+        // the index only narrows the search, it keeps every food that
+        // matching against all the names finds with the search in its name,
+        // dropping the scattered letters and short names padding them out
+        for search in ["m", "mi", "mil", "milk", "cheddar", "apple", "crisps", "wholemeal", "yogurt", "beef"] {
+            let candidates = super::candidates(&index, search).expect("words are indexed");
+            assert!(candidates.len() < foods.len() / 2, "{search}");
+            let indexed = names(found(&index, &foods, search));
+            assert!(indexed.len() >= 4, "{search}");
+            for f in everything(&foods, search).iter().filter(|f| f.display_name.to_lowercase().contains(search)) {
+                assert!(indexed.contains(&f.name), "{search}: {}", f.name);
+            }
+        }
+        // and with more than one word, only foods with something like
+        // each of them are matched
+        for search in ["semi skimmed milk", "english cheddar", "baked beans", "chicken breast", "olive oil"] {
+            let indexed = found(&index, &foods, search);
+            let name = indexed[0].display_name.to_lowercase();
+            assert!(super::tokens(search).iter().all(|w| name.contains(w)), "{search}: {name}");
+        }
+
+        // a typo still narrows and an abbreviation falls back on everything
+        let typo = found(&index, &foods, "chedar");
+        assert!(typo[0].display_name.contains("Cheddar"));
+        assert!(super::candidates(&index, "chdr").is_none());
+        let abbreviation = found(&index, &foods, "chdr");
+        assert_eq!(names(abbreviation), names(everything(&foods, "chdr")));
+
+        let allowed = super::search_foods(&index, &foods, "milk", |f| f.group == "Dairy", &Usage::default());
+        assert!(!allowed.is_empty());
+        assert!(allowed.iter().all(|m| foods[m.id].group == "Dairy"));
+    }
+
+    #[test]
+    fn synonyms() -> () {
+        let foods = test_foods().1;
+        let index = super::build_index(&foods);
+        let synonyms = super::default_synonyms();
+        assert_eq!(super::expand(synonyms, " Zucchini soup"), vec!["zucchini soup", "courgette soup"]);
+        assert_eq!(super::expand(synonyms, "soda bread"), vec!["soda bread"]);
+        assert_eq!(super::expand(synonyms, "Soda"), vec!["soda", "cola", "lemonade"]);
+        assert_eq!(super::expand(synonyms, "popcorn"), vec!["popcorn"]);
+        assert!(super::parse_synonyms("search,means\nzucchini,").is_err());
+
+        let first = |search: &str| found(&index, &foods, search)[0].name.clone();
+        assert!(first("zucchini").contains("Courgette"));
+        assert!(first("eggplant").to_lowercase().contains("aubergine"));
+        assert!(first("cilantro").contains("Coriander"));
+        assert!(first("brocoli").contains("Broccoli"));
+        assert!(first("ground beef").starts_with("Beef, mince"));
+        assert_eq!(first("coke"), "Cola");
+
+        // chips in either sense
+        let chips = names(found(&index, &foods, "chips"));
+        assert!(chips.iter().any(|n| n.starts_with("Potato crisps")));
+        assert!(chips.iter().any(|n| n.starts_with("Potato chips")));
+
+        // only the cofid name says what these are
+        assert_eq!(first("smarties"), "Smartie-type sweets");
+        assert!(first("weetabix").contains("Weetabix type"));
+    }
+
+    #[test]
+    fn scores_and_highlighting() -> () {
+        let foods = test_foods().1;
+        let index = super::build_index(&foods);
+
+        let matches = super::search_foods(&index, &foods, "cheddar", |_| true, &Usage::default());
+        assert!(matches.windows(2).all(|m| m[0].score >= m[1].score));
+        let cheddar = &foods[matches[0].id];
+        assert_eq!(cheddar.display_name, "English Cheddar");
+        assert_eq!(matches[0].indices, (8..15).collect::<Vec<usize>>());
+        assert_eq!(
+            super::highlight(&cheddar.display_name, &matches[0].indices),
+            vec![("English ".to_string(), false), ("Cheddar".to_string(), true)],
+        );
+
+        // only the foods worth showing, not always a full page
+        assert!(matches.len() < super::SEARCH_RESULTS);
+        assert_eq!(super::search_foods(&index, &foods, "qxzj", |_| true, &Usage::default()), vec![]);
+
+        // a synonym is highlighted where it matched and a cofid name match
+        // has nothing in the display name to highlight
+        let zucchini = &super::search_foods(&index, &foods, "zucchini", |_| true, &Usage::default())[0];
+        let courgette = &foods[zucchini.id].display_name;
+        let start = courgette.find("Courgette").expect("it is a courgette");
+        assert_eq!(zucchini.indices, (start..start + 9).collect::<Vec<usize>>());
+        let smarties = &super::search_foods(&index, &foods, "smarties", |_| true, &Usage::default())[0];
+        assert_eq!(smarties.indices.len(), 0);
+    }
+
+    #[test]
+    fn variants() -> () {
+        assert_eq!(
+            super::split_variant("Carrots, young, canned in water, re-heated, drained"),
+            ("carrots, young".to_string(), "canned in water, re-heated, drained".to_string()),
+        );
+        assert_eq!(super::split_variant("Milk, semi-skimmed, UHT").0, "milk, semi-skimmed");
+        assert_eq!(super::split_variant("Cod, in batter, fried in dripping, takeaway").0, "cod, in batter");
+        assert_eq!(super::split_variant("Fried rice, homemade").0, "fried rice");
+        assert_eq!(super::split_variant("Cola"), ("cola".to_string(), "".to_string()));
+
+        let foods = test_foods().1;
+        let index = super::build_index(&foods);
+        let search = |search: &str| super::group_variants(
+            &index,
+            &foods,
+            super::search_foods(&index, &foods, search, |_| true, &Usage::default()),
+            |_| true,
+        );
+        let carrots = search("carrots");
+        let labels = carrots[0].variants.iter().map(|(_, l)| l.as_str()).collect::<Vec<&str>>();
+        assert_eq!(labels, vec!["boiled in unsalted water", "canned in water, re-heated, drained", "raw"]);
+        assert!(carrots[0].variants.iter().any(|(i, _)| *i == carrots[0].best.id));
+        let matches = super::search_foods(&index, &foods, "carrots", |_| true, &Usage::default());
+        assert!(carrots.len() < matches.len());
+
+        let cod = search("raw cod");
+        assert_eq!(foods[cod[0].best.id].name, "Cod, flesh only, raw");
+        assert!(cod[0].variants.len() > 5);
+
+        // variants which aren't allowed aren't offered
+        let grouped = super::group_variants(
+            &index,
+            &foods,
+            super::search_foods(&index, &foods, "raw cod", |_| true, &Usage::default()),
+            |f| !f.name.contains("steamed"),
+        );
+        assert!(grouped[0].variants.len() < cod[0].variants.len());
+        assert!(grouped[0].variants.iter().all(|(_, l)| !l.contains("steamed")));
+    }
+
+    #[test]
+    fn personalised() -> () {
+        let foods = test_foods().1;
+        let index = super::build_index(&foods);
+        let milk = "Milk, semi-skimmed, pasteurised, average";
+        let first = |usage: &Usage| foods[super::search_foods(&index, &foods, "milk", |_| true, usage)[0].id].name.clone();
+        assert_ne!(first(&Usage::default()), milk);
+
+        // logged every day it comes first, and only for searches it matches
+        let usage = Usage {
+            foods: HashMap::from([(milk.to_string(), 1.)]),
+            weight: super::SearchSettings::default().personalisation,
+        };
+        assert_eq!(first(&usage), milk);
+        let matches = super::search_foods(&index, &foods, "milk", |_| true, &usage);
+        assert!(matches.windows(2).all(|m| m[0].score >= m[1].score));
+        let cheddar = super::search_foods(&index, &foods, "cheddar", |_| true, &usage);
+        assert!(cheddar.iter().all(|m| foods[m.id].name != milk));
+
+        // no weight is the plain ranking
+        let off = Usage { weight: 0., ..usage.clone() };
+        assert_eq!(
+            super::search_foods(&index, &foods, "milk", |_| true, &off),
+            super::search_foods(&index, &foods, "milk", |_| true, &Usage::default()),
+        );
+    }
+}
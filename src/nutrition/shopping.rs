@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use itertools::Itertools;
+use super::{Food, format_float};
+use super::plan::MealPlan;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShoppingItem {
+    pub name: String,
+    pub display_name: String,
+    pub emoji: String,
+    pub grams: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListFormat {
+    Text,
+    Markdown,
+}
+
+pub const LIST_FORMATS: [ListFormat; 2] = [
+    ListFormat::Text,
+    ListFormat::Markdown,
+];
+
+pub fn list_format_name(format: ListFormat) -> &'static str {
+    match format {
+        ListFormat::Text => "📝 Plain text",
+        ListFormat::Markdown => "⬇️ Markdown",
+    }
+}
+
+// everything in the plan added up per food and grouped by food group,
+// both in alphabetical order. foods missing from the dataset, e.g. once it
+// has been updated, are still listed under Other by name
+pub fn shopping_list(foods: &[Food], plan: &MealPlan) -> Vec<(String, Vec<ShoppingItem>)> {
+    let mut grams = HashMap::<&String, f32>::new();
+    for (name, g) in plan.days.iter().flatten().flat_map(|m| m.foods.iter()) {
+        *grams.entry(name).or_insert(0.) += g;
+    }
+    grams
+        .into_iter()
+        .map(|(name, g)| match foods.iter().find(|f| &f.name == name) {
+            Some(f) => (f.group.clone(), ShoppingItem {
+                name: f.name.clone(),
+                display_name: f.display_name.clone(),
+                emoji: f.emoji.clone(),
+                grams: g,
+            }),
+            None => ("Other".to_string(), ShoppingItem {
+                name: name.clone(),
+                display_name: name.clone(),
+                emoji: String::new(),
+                grams: g,
+            }),
+        })
+        .into_group_map()
+        .into_iter()
+        .map(|(group, items)| (
+            group,
+            items
+                .into_iter()
+                .sorted_by(|a, b| a.display_name.cmp(&b.display_name))
+                .collect::<Vec<ShoppingItem>>(),
+        ))
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .collect()
+}
+
+// "1.25 kg" reads better than "1250 g" on a shopping list
+pub fn format_amount(grams: f32) -> String {
+    match grams {
+        g if g >= 1000. => format!("{} kg", format_float(g / 1000.)),
+        g => format!("{} g", format_float(g)),
+    }
+}
+
+pub fn export_list(list: &[(String, Vec<ShoppingItem>)], format: ListFormat) -> String {
+    list
+        .iter()
+        .map(|(group, items)| {
+            let lines = items
+                .iter()
+                .map(|i| match format {
+                    ListFormat::Text => format!("  {} {}", format_amount(i.grams), i.display_name),
+                    ListFormat::Markdown => format!("- [ ] {} {}", format_amount(i.grams), i.display_name),
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            match format {
+                ListFormat::Text => format!("{group}\n{lines}"),
+                ListFormat::Markdown => format!("## {group}\n\n{lines}"),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::plan::{MealPlan, PlannedMeal, Meal};
+    use super::super::test_foods;
+
+    fn meal(meal: Meal, foods: &[(&str, f32)]) -> PlannedMeal {
+        PlannedMeal {
+            meal,
+            foods: foods.iter().map(|(n, g)| (n.to_string(), *g)).collect(),
+        }
+    }
+
+    #[test]
+    fn shopping() -> () {
+        let foods = test_foods().1;
+        let plan = MealPlan {
+            days: vec![
+                vec![
+                    meal(Meal::Breakfast, &[("Apples, eating, raw, flesh and skin", 150.), ("Bread, white, average", 75.)]),
+                    meal(Meal::Dinner, &[("Carrots, old, raw", 80.)]),
+                ],
+                vec![
+                    meal(Meal::Breakfast, &[("Bread, white, average", 75.)]),
+                    meal(Meal::Snacks, &[("Apples, eating, raw, flesh and skin", 900.), ("Zzyzx", 10.)]),
+                ],
+            ],
+        };
+        let list = super::shopping_list(&foods, &plan);
+        let groups = list.iter().map(|(g, _)| g.as_str()).collect::<Vec<&str>>();
+        assert_eq!(groups, vec!["Cereals & bread", "Fruit", "Other", "Vegetables"]);
+        assert_eq!(list[0].1[0].grams, 150.);
+        assert_eq!(list[1].1[0].grams, 1050.);
+        // a food the dataset doesn't have isn't left off the list
+        assert_eq!(list[2].1, vec![super::ShoppingItem {
+            name: "Zzyzx".to_string(),
+            display_name: "Zzyzx".to_string(),
+            emoji: String::new(),
+            grams: 10.,
+        }]);
+
+        let text = super::export_list(&list, super::ListFormat::Text);
+        assert!(text.starts_with("Cereals & bread\n  150 g White Bread\n\nFruit\n  1.05 kg "), "{text}");
+        let markdown = super::export_list(&list, super::ListFormat::Markdown);
+        assert!(markdown.starts_with("## Cereals & bread\n\n- [ ] 150 g White Bread\n\n## Fruit\n\n- [ ] 1.05 kg "), "{markdown}");
+    }
+}